@@ -0,0 +1,321 @@
+//! Multi-page and animated images
+//!
+//! libvips loads a GIF, WebP, TIFF or HEIF stack with `n=-1` as one tall image with frames
+//! stacked vertically. `page-height` gives the height of each frame, `delay` the display time of
+//! each frame in milliseconds, and `loop` the number of times to play, 0 meaning forever.
+use crate::{
+    bindings, error::Error, introspect::Operation, ops::Interesting, ops::Size, utils, v_value,
+    voption::VOption, Result, VipsImage,
+};
+
+pub const PAGE_HEIGHT_NAME: &str = "page-height";
+pub const DELAY_NAME: &str = "delay";
+pub const LOOP_NAME: &str = "loop";
+
+/// One page of a multi-page image
+#[derive(Debug)]
+pub struct Frame {
+    pub image: VipsImage,
+    /// Display time in milliseconds, 0 if the image has no delays
+    pub delay: i32,
+}
+
+impl VipsImage {
+    /// The number of frames stacked in this image, 1 for a single page image
+    pub fn frame_count(&self) -> i32 {
+        let page_height = self.get_page_height();
+        let height = self.get_height();
+        if page_height > 0 && page_height < height && height % page_height == 0 {
+            height / page_height
+        } else {
+            1
+        }
+    }
+
+    /// Whether this image holds more than one frame
+    pub fn is_animated(&self) -> bool {
+        self.frame_count() > 1
+    }
+
+    /// The display time of each frame in milliseconds, 0 for frames without one
+    pub fn frame_delays(&self) -> Vec<i32> {
+        let n_frames = self.frame_count() as usize;
+        let mut delays = self
+            .get_array_int(DELAY_NAME)
+            .unwrap_or_default();
+        delays.resize(
+            n_frames,
+            0,
+        );
+        delays
+    }
+
+    /// The number of times the animation plays, 0 meaning forever
+    pub fn loop_count(&self) -> i32 {
+        self.get_int(LOOP_NAME)
+            .unwrap_or(0)
+    }
+
+    /// Splits the image into its frames
+    pub fn frames(&self) -> Result<Vec<Frame>> {
+        let n_frames = self.frame_count();
+        let width = self.get_width();
+        let page_height = self.get_height() / n_frames;
+        self.frame_delays()
+            .into_iter()
+            .enumerate()
+            .map(
+                |(i, delay)| {
+                    Ok(Frame {
+                        image: self.extract_area(
+                            0,
+                            i as i32 * page_height,
+                            width,
+                            page_height,
+                        )?,
+                        delay,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    /// Stacks frames into one multi-page image, ready to save as an animation.
+    /// `delays` is in milliseconds and may be empty; `loop_count` 0 plays forever.
+    pub fn from_frames(frames: &[VipsImage], delays: &[i32], loop_count: i32) -> Result<VipsImage> {
+        let first = frames
+            .first()
+            .ok_or(Error::OperationError("No frames to join"))?;
+        let width = first.get_width();
+        let height = first.get_height();
+        let bands = first.get_bands();
+        if frames
+            .iter()
+            .any(|f| f.get_width() != width || f.get_height() != height || f.get_bands() != bands)
+        {
+            return Err(
+                Error::OperationError("All frames must have the same width, height and bands"),
+            );
+        }
+        if !delays.is_empty() && delays.len() != frames.len() {
+            return Err(Error::OperationError("There must be one delay per frame"));
+        }
+
+        // copy so that setting metadata cannot touch a cached arrayjoin result
+        let out = VipsImage::arrayjoin_with_opts(
+            frames,
+            VOption::new().set(
+                "across",
+                v_value!(1),
+            ),
+        )?
+        .copy()?;
+        out.set_page_layout(
+            height,
+            delays,
+            loop_count,
+        );
+        Ok(out)
+    }
+
+    /// Sets `page-height`, `delay` and `loop`. Only call this on an image you own, e.g. the result of `copy`.
+    pub(crate) fn set_page_layout(&self, page_height: i32, delays: &[i32], loop_count: i32) {
+        self.set_int(
+            PAGE_HEIGHT_NAME,
+            page_height,
+        );
+        if !delays.is_empty() {
+            self.set_array_int(
+                DELAY_NAME,
+                delays,
+            );
+        }
+        self.set_int(
+            LOOP_NAME,
+            loop_count,
+        );
+    }
+
+    /// Applies a function to every frame and stacks the results again, keeping delays and loop count
+    pub fn map_frames<F>(&self, mut f: F) -> Result<VipsImage>
+    where
+        F: FnMut(&VipsImage) -> Result<VipsImage>,
+    {
+        let frames = self.frames()?;
+        let delays: Vec<i32> = frames
+            .iter()
+            .map(|frame| frame.delay)
+            .collect();
+        let processed = frames
+            .iter()
+            .map(|frame| f(&frame.image))
+            .collect::<Result<Vec<_>>>()?;
+        let has_delays = self.get_typeof(DELAY_NAME) != 0;
+        VipsImage::from_frames(
+            &processed,
+            if has_delays { &delays } else { &[] },
+            self.loop_count(),
+        )
+    }
+
+    /// Loads every page of a GIF, WebP, TIFF, PDF or HEIF file, or the image itself for single page formats
+    pub fn new_from_file_all_pages(filename: &str) -> Result<VipsImage> {
+        let loader = unsafe {
+            let f = utils::new_c_string(filename)?;
+            let loader = bindings::vips_foreign_find_load(f.as_ptr());
+            if loader.is_null() {
+                bindings::vips_error_clear();
+                return Err(Error::IOError("No loader found for file"));
+            }
+            utils::c_string_lossy(loader)
+        };
+        let multi_page = Operation::find(&loader)?
+            .argument("n")
+            .is_some();
+        if multi_page {
+            VipsImage::new_from_file_with_opts(
+                filename,
+                VOption::new().set(
+                    "n",
+                    v_value!(-1),
+                ),
+            )
+        } else {
+            VipsImage::new_from_file(filename)
+        }
+    }
+
+    /// Makes a thumbnail of every page of a file, see `thumbnail_image_animated`
+    pub fn thumbnail_animated(
+        filename: &str,
+        width: i32,
+        height: Option<i32>,
+        crop: Interesting,
+    ) -> Result<VipsImage> {
+        VipsImage::new_from_file_all_pages(filename)?.thumbnail_image_animated(
+            width,
+            height,
+            crop,
+        )
+    }
+
+    /// Like `thumbnail_image`, but scales each frame separately and keeps delays and loop count.
+    /// Without `crop` each frame fits within `width` x `height`; with it each frame fills the box
+    /// and is cut at the same place in every frame, so content-aware modes fall back to the centre.
+    pub fn thumbnail_image_animated(
+        &self,
+        width: i32,
+        height: Option<i32>,
+        crop: Interesting,
+    ) -> Result<VipsImage> {
+        if !self.is_animated() {
+            let mut option = VOption::new().set(
+                "crop",
+                v_value!(crop as i32),
+            );
+            if let Some(height) = height {
+                option = option.set(
+                    "height",
+                    v_value!(height),
+                );
+            }
+            return self.thumbnail_image_with_opts(
+                width,
+                option,
+            );
+        }
+
+        let frame_width = self.get_width() as f64;
+        let frame_height = (self.get_height() / self.frame_count()) as f64;
+        let hscale = width as f64 / frame_width;
+        let vscale = height.map_or(
+            hscale,
+            |height| height as f64 / frame_height,
+        );
+        let cropping = !matches!(
+            crop,
+            Interesting::None | Interesting::All
+        );
+        let scale = if cropping { hscale.max(vscale) } else { hscale.min(vscale) };
+        let scaled_width = ((frame_width * scale).round() as i32).max(1);
+        let scaled_height = ((frame_height * scale).round() as i32).max(1);
+        let crop_width = width.min(scaled_width);
+        let crop_height = height
+            .unwrap_or(scaled_height)
+            .min(scaled_height);
+        let (left, top) = match crop {
+            Interesting::Low => (0, 0),
+            Interesting::High => (
+                scaled_width - crop_width,
+                scaled_height - crop_height,
+            ),
+            _ => (
+                (scaled_width - crop_width) / 2,
+                (scaled_height - crop_height) / 2,
+            ),
+        };
+
+        self.map_frames(|frame| {
+            let scaled = frame.thumbnail_image_with_opts(
+                scaled_width,
+                VOption::new()
+                    .set(
+                        "height",
+                        v_value!(scaled_height),
+                    )
+                    .set(
+                        "size",
+                        v_value!(Size::Force as i32),
+                    ),
+            )?;
+            if cropping {
+                scaled.extract_area(
+                    left,
+                    top,
+                    crop_width,
+                    crop_height,
+                )
+            } else {
+                Ok(scaled)
+            }
+        })
+    }
+
+    /// Like `resize`, but scales each frame separately and keeps delays and loop count
+    pub fn resize_animated(&self, scale: f64) -> Result<VipsImage> {
+        if !self.is_animated() {
+            return self.resize(scale);
+        }
+        self.map_frames(|frame| frame.resize(scale))
+    }
+
+    /// Crops the same area from every frame. Fails if the area does not fit within one frame.
+    pub fn crop_animated(&self, left: i32, top: i32, width: i32, height: i32) -> Result<VipsImage> {
+        if !self.is_animated() {
+            return self.extract_area(
+                left,
+                top,
+                width,
+                height,
+            );
+        }
+        let frame_height = self.get_height() / self.frame_count();
+        if left < 0
+            || top < 0
+            || width <= 0
+            || height <= 0
+            || left + width > self.get_width()
+            || top + height > frame_height
+        {
+            return Err(Error::OperationError("Crop crosses a frame boundary"));
+        }
+        self.map_frames(|frame| {
+            frame.extract_area(
+                left,
+                top,
+                width,
+                height,
+            )
+        })
+    }
+}
@@ -0,0 +1,627 @@
+//! Command line tool mirroring the `vips` command, built on this crate.
+//!
+//! ```text
+//! vips-rs thumbnail in.jpg out.png 512 --crop centre
+//! vips-rs thumbnail --help
+//! vips-rs --list
+//! ```
+use libvips::{
+    bindings,
+    introspect::{self, Argument, ArgumentType, Operation},
+    pipeline::Value,
+    utils,
+    voption::{call, VOption, VipsValue},
+    Vips, VipsBlob, VipsImage, VipsInterpolate, VipsSource, VipsTarget,
+};
+use std::ffi::CStr;
+use std::process::ExitCode;
+use std::ptr::null_mut;
+
+const USAGE: &str = "usage:
+   vips-rs <operation> [arguments ...] [--option-name option-value ...]
+   vips-rs <operation> --help
+   vips-rs --list";
+
+/// Command line values, converted into vips values once everything is parsed
+enum Input {
+    Value(Value),
+    Image(VipsImage),
+    Images(Vec<VipsImage>),
+    Buffer(Vec<u8>),
+    Source(VipsSource),
+    Target(VipsTarget),
+    Interpolate(VipsInterpolate),
+}
+
+enum Output {
+    Image(
+        VipsImage,
+        String,
+    ),
+    Blob(
+        VipsBlob,
+        String,
+    ),
+    Bool(bool),
+    Int(i32),
+    Enum(
+        i32,
+        String,
+    ),
+    Double(f64),
+    DoubleArray(Vec<f64>),
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if let Err(e) = Vips::init(
+        &args[0],
+        false,
+    ) {
+        eprintln!(
+            "vips-rs: {}",
+            e
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let result = run(&args[1..]);
+    let code = match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!(
+                "vips-rs: {}",
+                message
+            );
+            if let Ok(buffer) = Vips::error_buffer() {
+                if !buffer.is_empty() {
+                    eprint!(
+                        "{}",
+                        buffer
+                    );
+                }
+            }
+            ExitCode::FAILURE
+        }
+    };
+    Vips::shutdown();
+    code
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let nickname = match args.first() {
+        None => {
+            println!(
+                "{}",
+                USAGE
+            );
+            return Ok(());
+        }
+        Some(arg) if arg == "--help" || arg == "-h" => {
+            println!(
+                "{}",
+                USAGE
+            );
+            return Ok(());
+        }
+        Some(arg) if arg == "--list" || arg == "-l" => {
+            list();
+            return Ok(());
+        }
+        Some(arg) => arg,
+    };
+
+    let operation = Operation::find(nickname).map_err(|e| e.to_string())?;
+    if args[1..]
+        .iter()
+        .any(|arg| arg == "--help" || arg == "-h")
+    {
+        print!(
+            "{}",
+            help(&operation)
+        );
+        return Ok(());
+    }
+
+    execute(
+        &operation,
+        &args[1..],
+    )
+}
+
+fn list() {
+    for nickname in introspect::operations() {
+        match Operation::find(&nickname) {
+            Ok(operation) => println!(
+                "{:<24} - {}",
+                operation.nickname, operation.description
+            ),
+            Err(_) => println!(
+                "{}",
+                nickname
+            ),
+        }
+    }
+}
+
+fn type_name(kind: &ArgumentType) -> String {
+    match kind {
+        ArgumentType::Bool => "gboolean".to_string(),
+        ArgumentType::Int => "gint".to_string(),
+        ArgumentType::Uint64 => "guint64".to_string(),
+        ArgumentType::Double => "gdouble".to_string(),
+        ArgumentType::String => "gchararray".to_string(),
+        ArgumentType::Enum {
+            name,
+            ..
+        }
+        | ArgumentType::Flags {
+            name,
+            ..
+        }
+        | ArgumentType::Other(name) => name.clone(),
+        ArgumentType::Image => "VipsImage".to_string(),
+        ArgumentType::ImageArray => "VipsArrayImage".to_string(),
+        ArgumentType::IntArray => "VipsArrayInt".to_string(),
+        ArgumentType::DoubleArray => "VipsArrayDouble".to_string(),
+        ArgumentType::Blob => "VipsBlob".to_string(),
+        ArgumentType::Source => "VipsSource".to_string(),
+        ArgumentType::Target => "VipsTarget".to_string(),
+        ArgumentType::Interpolate => "VipsInterpolate".to_string(),
+    }
+}
+
+fn describe(arg: &Argument) -> String {
+    let mut line = format!(
+        "   {:<16} - {}, {} {}\n",
+        arg.name,
+        arg.blurb,
+        if arg.output { "output" } else { "input" },
+        type_name(&arg.kind)
+    );
+    match &arg.kind {
+        ArgumentType::Enum {
+            nicks,
+            ..
+        } => line.push_str(&format!(
+            "{:<21}allowed enums: {}\n",
+            "",
+            nicks.join(", ")
+        )),
+        ArgumentType::Flags {
+            nicks,
+            ..
+        } => line.push_str(&format!(
+            "{:<21}allowed flags: {}\n",
+            "",
+            nicks.join(", ")
+        )),
+        _ => {}
+    }
+    line
+}
+
+/// Required arguments which are given on the command line, in order
+fn positional(operation: &Operation) -> Vec<&Argument> {
+    operation
+        .arguments
+        .iter()
+        .filter(|arg| {
+            arg.required
+                && !arg.deprecated
+                && (arg.input
+                    || matches!(
+                        arg.kind,
+                        ArgumentType::Image | ArgumentType::Blob
+                    ))
+        })
+        .collect()
+}
+
+fn help(operation: &Operation) -> String {
+    let required = positional(operation);
+    let mut text = format!(
+        "{} - {}\nusage:\n   {} {} [--option-name option-value ...]\nwhere:\n",
+        operation.nickname,
+        operation.description,
+        operation.nickname,
+        required
+            .iter()
+            .map(|arg| arg
+                .name
+                .as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    for arg in required {
+        text.push_str(&describe(
+            arg,
+        ));
+    }
+
+    for arg in operation
+        .outputs()
+        .filter(|arg| arg.required && !is_file_output(arg))
+    {
+        text.push_str(&describe(
+            arg,
+        ));
+    }
+
+    let optional: Vec<&Argument> = operation
+        .arguments
+        .iter()
+        .filter(|arg| !arg.required && !arg.deprecated)
+        .collect();
+    if !optional.is_empty() {
+        text.push_str("optional arguments:\n");
+        for arg in optional {
+            text.push_str(&describe(
+                arg,
+            ));
+        }
+    }
+    text
+}
+
+fn is_file_output(arg: &Argument) -> bool {
+    arg.output
+        && matches!(
+            arg.kind,
+            ArgumentType::Image | ArgumentType::Blob
+        )
+}
+
+fn find_argument<'a>(operation: &'a Operation, name: &str) -> Option<&'a Argument> {
+    let name = name.replace('-', "_");
+    operation
+        .arguments
+        .iter()
+        .find(|arg| {
+            arg.name
+                .replace('-', "_")
+                == name
+        })
+}
+
+fn parse_input(arg: &Argument, text: &str) -> Result<Input, String> {
+    let input = match arg.kind {
+        ArgumentType::Image => Input::Image(
+            VipsImage::new_from_file(text).map_err(|e| {
+                format!(
+                    "{}: {}",
+                    text, e
+                )
+            })?,
+        ),
+        ArgumentType::ImageArray => Input::Images(
+            text.split_whitespace()
+                .map(
+                    |filename| {
+                        VipsImage::new_from_file(filename).map_err(|e| {
+                            format!(
+                                "{}: {}",
+                                filename, e
+                            )
+                        })
+                    },
+                )
+                .collect::<Result<_, _>>()?,
+        ),
+        ArgumentType::Blob => Input::Buffer(
+            std::fs::read(text).map_err(|e| {
+                format!(
+                    "{}: {}",
+                    text, e
+                )
+            })?,
+        ),
+        ArgumentType::Source => {
+            Input::Source(VipsSource::new_from_file(text).map_err(|e| e.to_string())?)
+        }
+        ArgumentType::Target => {
+            Input::Target(VipsTarget::new_to_file(text).map_err(|e| e.to_string())?)
+        }
+        ArgumentType::Interpolate => {
+            Input::Interpolate(VipsInterpolate::new_from_name(text).map_err(|e| e.to_string())?)
+        }
+        _ => Input::Value(Value::parse(arg, text).map_err(|e| e.to_string())?),
+    };
+    Ok(input)
+}
+
+fn new_output(arg: &Argument, filename: Option<&str>) -> Result<Output, String> {
+    let output = match arg.kind {
+        ArgumentType::Image => Output::Image(
+            VipsImage::from(null_mut()),
+            filename
+                .ok_or(format!(
+                    "missing filename for `{}`",
+                    arg.name
+                ))?
+                .to_string(),
+        ),
+        ArgumentType::Blob => Output::Blob(
+            VipsBlob::from(null_mut()),
+            filename
+                .ok_or(format!(
+                    "missing filename for `{}`",
+                    arg.name
+                ))?
+                .to_string(),
+        ),
+        ArgumentType::Bool => Output::Bool(false),
+        ArgumentType::Int => Output::Int(0),
+        ArgumentType::Enum {
+            ref name,
+            ..
+        } => Output::Enum(
+            0,
+            name.clone(),
+        ),
+        ArgumentType::Double => Output::Double(0.0),
+        ArgumentType::DoubleArray => Output::DoubleArray(Vec::new()),
+        _ => {
+            return Err(format!(
+                "output `{}` of type {} is not supported",
+                arg.name,
+                type_name(&arg.kind)
+            ))
+        }
+    };
+    Ok(output)
+}
+
+fn execute(operation: &Operation, args: &[String]) -> Result<(), String> {
+    let mut inputs: Vec<(
+        String,
+        Input,
+    )> = Vec::new();
+    let mut outputs: Vec<(
+        String,
+        Output,
+    )> = Vec::new();
+
+    let mut positionals = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let Some(option) = arg.strip_prefix("--") else {
+            positionals.push(arg.as_str());
+            continue;
+        };
+
+        let (name, inline_value) = match option.split_once('=') {
+            Some((name, value)) => (
+                name,
+                Some(value.to_string()),
+            ),
+            None => (
+                option,
+                None,
+            ),
+        };
+        let argument = find_argument(
+            operation,
+            name,
+        )
+        .ok_or(format!(
+            "{} has no argument `{}`",
+            operation.nickname, name
+        ))?;
+
+        if argument.output {
+            let filename = if is_file_output(argument) {
+                inline_value.or_else(|| {
+                    iter.next()
+                        .cloned()
+                })
+            } else {
+                None
+            };
+            outputs.push((
+                argument
+                    .name
+                    .clone(),
+                new_output(
+                    argument,
+                    filename.as_deref(),
+                )?,
+            ));
+            continue;
+        }
+
+        let value = match (
+            inline_value,
+            &argument.kind,
+        ) {
+            (Some(value), _) => value,
+            (None, ArgumentType::Bool) => "true".to_string(),
+            (None, _) => iter
+                .next()
+                .cloned()
+                .ok_or(format!(
+                    "missing value for `--{}`",
+                    name
+                ))?,
+        };
+        inputs.push((
+            argument
+                .name
+                .clone(),
+            parse_input(
+                argument,
+                &value,
+            )?,
+        ));
+    }
+
+    let required = positional(operation);
+    if positionals.len() != required.len() {
+        return Err(format!(
+            "{} expects {} arguments ({}), got {}\n{}",
+            operation.nickname,
+            required.len(),
+            required
+                .iter()
+                .map(|arg| arg
+                    .name
+                    .as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            positionals.len(),
+            help(operation)
+        ));
+    }
+    for (arg, text) in required
+        .iter()
+        .zip(positionals)
+    {
+        if arg.output {
+            outputs.push((
+                arg.name
+                    .clone(),
+                new_output(
+                    arg,
+                    Some(text),
+                )?,
+            ));
+        } else {
+            inputs.push((
+                arg.name
+                    .clone(),
+                parse_input(arg, text)?,
+            ));
+        }
+    }
+    // required outputs such as the value of `avg` are printed rather than given on the command line
+    for arg in operation
+        .outputs()
+        .filter(|arg| arg.required && !is_file_output(arg))
+    {
+        outputs.push((
+            arg.name
+                .clone(),
+            new_output(arg, None)?,
+        ));
+    }
+
+    let mut option = VOption::new();
+    for (name, input) in &inputs {
+        let value = match input {
+            Input::Value(value) => value.as_vips_value(),
+            Input::Image(image) => VipsValue::Image(image),
+            Input::Images(images) => VipsValue::ImageArray(images),
+            Input::Buffer(buffer) => VipsValue::Buffer(buffer),
+            Input::Source(source) => VipsValue::Source(source),
+            Input::Target(target) => VipsValue::Target(target),
+            Input::Interpolate(interpolate) => VipsValue::Interpolate(interpolate),
+        };
+        option.add(
+            name, value,
+        );
+    }
+    for (name, output) in &mut outputs {
+        let value = match output {
+            Output::Image(image, _) => VipsValue::MutImage(image),
+            Output::Blob(blob, _) => VipsValue::MutBlob(blob),
+            Output::Bool(value) => VipsValue::MutBool(value),
+            Output::Int(value) | Output::Enum(value, _) => VipsValue::MutInt(value),
+            Output::Double(value) => VipsValue::MutDouble(value),
+            Output::DoubleArray(value) => VipsValue::MutDoubleArray(value),
+        };
+        option.add(
+            name, value,
+        );
+    }
+
+    if call(
+        &operation.nickname,
+        option,
+    ) != 0
+    {
+        return Err(format!(
+            "{} failed",
+            operation.nickname
+        ));
+    }
+
+    for (name, output) in outputs {
+        // required values are printed bare, like `vips avg`, optional ones with their name
+        let label = match operation.argument(&name) {
+            Some(arg) if arg.required => String::new(),
+            _ => format!(
+                "{}: ",
+                name
+            ),
+        };
+        match output {
+            Output::Image(image, filename) => image
+                .image_write_to_file(&filename)
+                .map_err(|e| {
+                    format!(
+                        "{}: {}",
+                        filename, e
+                    )
+                })?,
+            Output::Blob(blob, filename) => {
+                let bytes: Vec<u8> = blob.into();
+                std::fs::write(
+                    &filename,
+                    bytes,
+                )
+                .map_err(|e| {
+                    format!(
+                        "{}: {}",
+                        filename, e
+                    )
+                })?
+            }
+            Output::Bool(value) => println!(
+                "{}{}",
+                label, value
+            ),
+            Output::Int(value) => println!(
+                "{}{}",
+                label, value
+            ),
+            Output::Enum(value, type_name) => println!(
+                "{}{}",
+                label,
+                enum_nick(
+                    &type_name,
+                    value
+                )
+            ),
+            Output::Double(value) => println!(
+                "{}{}",
+                label, value
+            ),
+            Output::DoubleArray(values) => println!(
+                "{}{}",
+                label,
+                values
+                    .iter()
+                    .map(f64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn enum_nick(type_name: &str, value: i32) -> String {
+    unsafe {
+        let nick = bindings::vips_enum_nick(
+            utils::get_g_type(type_name),
+            value,
+        );
+        if nick.is_null() {
+            value.to_string()
+        } else {
+            CStr::from_ptr(nick)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Drawing
+//!
+//! The `draw_*` operations change the pixels of their image in place, so calling them on an
+//! image other handles share, or on a lazily computed one, changes those too. A [`Canvas`] owns a
+//! private memory copy instead, draws through `&mut self`, and hands the result back with
+//! [`Canvas::finish`].
+use crate::{
+    ops::{BandFormat, CombineMode},
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+
+/// One drawing primitive, for `Canvas::draw`
+#[derive(Debug)]
+pub enum Shape<'a> {
+    Rect {
+        ink: Vec<f64>,
+        left: i32,
+        top: i32,
+        width: i32,
+        height: i32,
+        fill: bool,
+    },
+    Circle {
+        ink: Vec<f64>,
+        cx: i32,
+        cy: i32,
+        radius: i32,
+        fill: bool,
+    },
+    Line {
+        ink: Vec<f64>,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+    },
+    /// Fills the area of similar colour around a point
+    Flood {
+        ink: Vec<f64>,
+        x: i32,
+        y: i32,
+    },
+    Image {
+        image: &'a VipsImage,
+        x: i32,
+        y: i32,
+        mode: CombineMode,
+    },
+    /// Paints `ink` through a one band mask, 255 meaning fully inked
+    Mask {
+        ink: Vec<f64>,
+        mask: &'a VipsImage,
+        x: i32,
+        y: i32,
+    },
+    /// Blurs an area
+    Smudge {
+        left: i32,
+        top: i32,
+        width: i32,
+        height: i32,
+    },
+}
+
+/// An image in memory which can be drawn on
+#[derive(Debug)]
+pub struct Canvas {
+    image: VipsImage,
+}
+
+impl Canvas {
+    /// Renders a private copy of the image to draw on. The image itself is left unchanged.
+    pub fn new(image: &VipsImage) -> Result<Canvas> {
+        // image_copy_memory returns memory images as they are, so go through a copy first
+        Ok(Canvas {
+            image: VipsImage::image_copy_memory(image.copy()?)?,
+        })
+    }
+
+    /// An 8-bit canvas with one band per value of `background`, filled with it
+    pub fn blank(width: i32, height: i32, background: &[f64]) -> Result<Canvas> {
+        let bands = background
+            .len()
+            .max(1);
+        let black = VipsImage::black_with_opts(
+            width,
+            height,
+            VOption::new().set(
+                "bands",
+                v_value!(bands as i32),
+            ),
+        )?;
+        let zeros = vec![0.0; bands];
+        Canvas::new(
+            &black
+                .linear(
+                    &zeros,
+                    background,
+                )?
+                .cast(BandFormat::Uchar)?,
+        )
+    }
+
+    pub fn width(&self) -> i32 {
+        self.image
+            .get_width()
+    }
+
+    pub fn height(&self) -> i32 {
+        self.image
+            .get_height()
+    }
+
+    pub fn bands(&self) -> i32 {
+        self.image
+            .get_bands()
+    }
+
+    pub fn rect(
+        &mut self,
+        ink: &[f64],
+        left: i32,
+        top: i32,
+        width: i32,
+        height: i32,
+        fill: bool,
+    ) -> Result<&mut Canvas> {
+        self.image
+            .draw_rect_with_opts(
+                ink,
+                left,
+                top,
+                width,
+                height,
+                VOption::new().set(
+                    "fill",
+                    v_value!(fill),
+                ),
+            )?;
+        Ok(self)
+    }
+
+    pub fn circle(
+        &mut self,
+        ink: &[f64],
+        cx: i32,
+        cy: i32,
+        radius: i32,
+        fill: bool,
+    ) -> Result<&mut Canvas> {
+        self.image
+            .draw_circle_with_opts(
+                ink,
+                cx,
+                cy,
+                radius,
+                VOption::new().set(
+                    "fill",
+                    v_value!(fill),
+                ),
+            )?;
+        Ok(self)
+    }
+
+    pub fn line(&mut self, ink: &[f64], x1: i32, y1: i32, x2: i32, y2: i32) -> Result<&mut Canvas> {
+        self.image
+            .draw_line(
+                ink, x1, y1, x2, y2,
+            )?;
+        Ok(self)
+    }
+
+    /// Fills the area of similar colour around a point
+    pub fn flood(&mut self, ink: &[f64], x: i32, y: i32) -> Result<&mut Canvas> {
+        self.image
+            .draw_flood(ink, x, y)?;
+        Ok(self)
+    }
+
+    /// Paints another image, with `CombineMode::Set` to replace the pixels or `Add` to add to them
+    pub fn image(
+        &mut self,
+        image: &VipsImage,
+        x: i32,
+        y: i32,
+        mode: CombineMode,
+    ) -> Result<&mut Canvas> {
+        self.image
+            .draw_image_with_opts(
+                image,
+                x,
+                y,
+                VOption::new().set(
+                    "mode",
+                    v_value!(mode as i32),
+                ),
+            )?;
+        Ok(self)
+    }
+
+    /// Paints `ink` through a one band mask, 255 meaning fully inked
+    pub fn mask(&mut self, ink: &[f64], mask: &VipsImage, x: i32, y: i32) -> Result<&mut Canvas> {
+        self.image
+            .draw_mask(
+                ink, mask, x, y,
+            )?;
+        Ok(self)
+    }
+
+    /// Blurs an area
+    pub fn smudge(&mut self, left: i32, top: i32, width: i32, height: i32) -> Result<&mut Canvas> {
+        self.image
+            .draw_smudge(
+                left,
+                top,
+                width,
+                height,
+            )?;
+        Ok(self)
+    }
+
+    /// Draws many shapes in order, stopping at the first that fails
+    pub fn draw<'a>(
+        &mut self,
+        shapes: impl IntoIterator<Item = &'a Shape<'a>>,
+    ) -> Result<&mut Canvas> {
+        for shape in shapes {
+            match shape {
+                Shape::Rect {
+                    ink,
+                    left,
+                    top,
+                    width,
+                    height,
+                    fill,
+                } => self.rect(
+                    ink,
+                    *left,
+                    *top,
+                    *width,
+                    *height,
+                    *fill,
+                )?,
+                Shape::Circle {
+                    ink,
+                    cx,
+                    cy,
+                    radius,
+                    fill,
+                } => self.circle(
+                    ink,
+                    *cx,
+                    *cy,
+                    *radius,
+                    *fill,
+                )?,
+                Shape::Line {
+                    ink,
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                } => self.line(
+                    ink, *x1, *y1, *x2, *y2,
+                )?,
+                Shape::Flood {
+                    ink,
+                    x,
+                    y,
+                } => self.flood(
+                    ink, *x, *y,
+                )?,
+                Shape::Image {
+                    image,
+                    x,
+                    y,
+                    mode,
+                } => self.image(
+                    image, *x, *y, *mode,
+                )?,
+                Shape::Mask {
+                    ink,
+                    mask,
+                    x,
+                    y,
+                } => self.mask(
+                    ink, mask, *x, *y,
+                )?,
+                Shape::Smudge {
+                    left,
+                    top,
+                    width,
+                    height,
+                } => self.smudge(
+                    *left,
+                    *top,
+                    *width,
+                    *height,
+                )?,
+            };
+        }
+        Ok(self)
+    }
+
+    /// Stops drawing and returns the image, which nothing can change any more
+    pub fn finish(self) -> VipsImage {
+        self.image
+    }
+}
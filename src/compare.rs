@@ -0,0 +1,170 @@
+//! Image quality metrics
+//!
+//! [`Metrics`] compares a processed image with its original using plain vips operations:
+//! squared error over every band, SSIM on luminance, and CIEDE2000 colour difference in Lab.
+use crate::{
+    error::Error,
+    ops::{BandFormat, Interpretation},
+    v_value,
+    voption::VOption,
+    Result, Vips, VipsImage,
+};
+
+const SSIM_SIGMA: f64 = 1.5;
+
+/// How close two images are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    /// Mean squared error over all pixels and bands, in pixel values
+    pub mse: f64,
+    /// Peak signal-to-noise ratio in dB, infinite for identical images
+    pub psnr: f64,
+    /// Mean structural similarity of the luminance, 1 for identical images
+    pub ssim: f64,
+    /// The largest CIEDE2000 colour difference of any pixel, or `None` when the images cannot
+    /// be converted to Lab, e.g. CMYK or multiband images
+    pub max_de00: Option<f64>,
+}
+
+impl VipsImage {
+    /// Compares this image with another of the same size and number of bands
+    pub fn compare(&self, other: &VipsImage) -> Result<Metrics> {
+        if self.get_width() != other.get_width()
+            || self.get_height() != other.get_height()
+            || self.get_bands() != other.get_bands()
+        {
+            return Err(Error::OperationError("Images must have the same width, height and bands"));
+        }
+
+        let peak = peak_value(self.get_format()?);
+        let a = self.cast(BandFormat::Double)?;
+        let b = other.cast(BandFormat::Double)?;
+        let difference = a.subtract(&b)?;
+        let mse = difference
+            .multiply(&difference)?
+            .avg()?;
+        let psnr = if mse == 0.0 { f64::INFINITY } else { 10.0 * (peak * peak / mse).log10() };
+
+        Ok(Metrics {
+            mse,
+            psnr,
+            ssim: ssim(
+                &luminance(self)?,
+                &luminance(other)?,
+                peak,
+            )?,
+            max_de00: max_de00(
+                self, other,
+            ),
+        })
+    }
+}
+
+/// The largest value of a band format, or 1 for float images
+fn peak_value(format: BandFormat) -> f64 {
+    match format {
+        BandFormat::Uchar => u8::MAX as f64,
+        BandFormat::Char => i8::MAX as f64,
+        BandFormat::Ushort => u16::MAX as f64,
+        BandFormat::Short => i16::MAX as f64,
+        BandFormat::Uint => u32::MAX as f64,
+        BandFormat::Int => i32::MAX as f64,
+        _ => 1.0,
+    }
+}
+
+/// The first three bands, or the only band, without alpha
+fn colour_bands(image: &VipsImage) -> Result<VipsImage> {
+    let bands = if image.get_bands() >= 3 { 3 } else { 1 };
+    image.extract_band_with_opts(
+        0,
+        VOption::new().set(
+            "n",
+            v_value!(bands),
+        ),
+    )
+}
+
+/// Luminance in the pixel range of the image, as double
+fn luminance(image: &VipsImage) -> Result<VipsImage> {
+    let colour = colour_bands(image)?;
+    if colour.get_bands() == 1 {
+        return colour.cast(BandFormat::Double);
+    }
+    // Rec. 601 weights, which keeps the pixel range unlike a colourspace conversion
+    colour
+        .recomb(
+            &VipsImage::new_matrix_from_array(
+                3,
+                1,
+                &[0.299, 0.587, 0.114],
+            )?,
+        )?
+        .cast(BandFormat::Double)
+}
+
+fn lab(image: &VipsImage) -> Result<VipsImage> {
+    colour_bands(image)?.colourspace(Interpretation::Lab)
+}
+
+/// The largest CIEDE2000 difference, if both images have a route to Lab
+fn max_de00(a: &VipsImage, b: &VipsImage) -> Option<f64> {
+    match lab(a)
+        .and_then(|a| a.dE00(&lab(b)?))
+        .and_then(|difference| difference.max())
+    {
+        Ok(max) => Some(max),
+        Err(_) => {
+            Vips::error_clear();
+            None
+        }
+    }
+}
+
+fn blur(image: &VipsImage) -> Result<VipsImage> {
+    image.gaussblur(SSIM_SIGMA)
+}
+
+/// SSIM with a Gaussian window of sigma 1.5, K1 = 0.01 and K2 = 0.03
+fn ssim(x: &VipsImage, y: &VipsImage, peak: f64) -> Result<f64> {
+    let c1 = (0.01 * peak).powi(2);
+    let c2 = (0.03 * peak).powi(2);
+
+    let mu_x = blur(x)?;
+    let mu_y = blur(y)?;
+    let mu_x2 = mu_x.multiply(&mu_x)?;
+    let mu_y2 = mu_y.multiply(&mu_y)?;
+    let mu_xy = mu_x.multiply(&mu_y)?;
+    let sigma_x2 = blur(&x.multiply(x)?)?.subtract(&mu_x2)?;
+    let sigma_y2 = blur(&y.multiply(y)?)?.subtract(&mu_y2)?;
+    let sigma_xy = blur(&x.multiply(y)?)?.subtract(&mu_xy)?;
+
+    let numerator = mu_xy
+        .linear(
+            &[2.0],
+            &[c1],
+        )?
+        .multiply(
+            &sigma_xy.linear(
+                &[2.0],
+                &[c2],
+            )?,
+        )?;
+    let denominator = mu_x2
+        .add(&mu_y2)?
+        .linear(
+            &[1.0],
+            &[c1],
+        )?
+        .multiply(
+            &sigma_x2
+                .add(&sigma_y2)?
+                .linear(
+                    &[1.0],
+                    &[c2],
+                )?,
+        )?;
+    numerator
+        .divide(&denominator)?
+        .avg()
+}
@@ -0,0 +1,699 @@
+//! Encoding
+//!
+//! [`Encoder`] holds a format and its typed saver options, and `VipsImage::save`,
+//! `save_buffer` and `save_target` write with the matching `*save` operation.
+//! [`choose_output_format`] picks the format to send a client from its `Accept` header and what
+//! the local libvips can write, and `VipsImage::encode` writes it in one call.
+use crate::{
+    error::Error,
+    format::{ForeignSupport, ImageFormat},
+    ops::{ForeignHeifCompression, ForeignKeep, ForeignSubsample, ForeignTiffCompression},
+    v_value,
+    voption::VOption,
+    Result, Vips, VipsImage, VipsTarget,
+};
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+static SAVERS: OnceLock<Vec<ForeignSupport>> = OnceLock::new();
+
+/// A format images can be encoded to in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+    Heif,
+    Jxl,
+    Gif,
+    Tiff,
+}
+
+impl OutputFormat {
+    pub fn image_format(&self) -> ImageFormat {
+        match self {
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Webp => ImageFormat::Webp,
+            OutputFormat::Avif => ImageFormat::Avif,
+            OutputFormat::Heif => ImageFormat::Heif,
+            OutputFormat::Jxl => ImageFormat::Jxl,
+            OutputFormat::Gif => ImageFormat::Gif,
+            OutputFormat::Tiff => ImageFormat::Tiff,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        self.image_format()
+            .mime_type()
+            .unwrap_or("application/octet-stream")
+    }
+
+    /// Whether the local libvips has a buffer saver for this format
+    pub fn is_available(&self) -> bool {
+        SAVERS
+            .get_or_init(Vips::supported_savers)
+            .iter()
+            .any(|saver| saver.buffer && saver.supports(self.image_format()))
+    }
+}
+
+/// How hard to compress. PNG and GIF ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    /// The saver's default, usually Q 75
+    #[default]
+    Default,
+    /// Lossless where the format supports it, Q 100 for JPEG
+    Lossless,
+    /// Q factor, 1 to 100
+    Value(i32),
+}
+
+/// Picks the best format a client accepts, given the `Accept` header of its request.
+/// AVIF and WebP are used only when listed explicitly and the local libvips can write them;
+/// otherwise images with alpha become PNG and the rest JPEG. Animations become WebP or GIF.
+pub fn choose_output_format(accept: &str, has_alpha: bool, is_animated: bool) -> OutputFormat {
+    let accepted: Vec<&str> = accept
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let mime = params
+                .next()?
+                .trim();
+            let refused = params.any(|param| {
+                match param
+                    .trim()
+                    .split_once('=')
+                {
+                    Some(("q", q)) => q
+                        .trim()
+                        .parse::<f64>()
+                        .is_ok_and(|q| q <= 0.0),
+                    _ => false,
+                }
+            });
+            if refused {
+                None
+            } else {
+                Some(mime)
+            }
+        })
+        .collect();
+    let usable = |format: OutputFormat| {
+        accepted
+            .iter()
+            .any(|mime| mime.eq_ignore_ascii_case(format.mime_type()))
+            && format.is_available()
+    };
+
+    if is_animated {
+        if usable(OutputFormat::Webp) {
+            OutputFormat::Webp
+        } else {
+            OutputFormat::Gif
+        }
+    } else if usable(OutputFormat::Avif) {
+        OutputFormat::Avif
+    } else if usable(OutputFormat::Webp) {
+        OutputFormat::Webp
+    } else if has_alpha {
+        OutputFormat::Png
+    } else {
+        OutputFormat::Jpeg
+    }
+}
+
+/// A format and its saver options. `Encoder::from(OutputFormat)` gives the saver defaults.
+/// `strip` drops all metadata, including the ICC profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoder {
+    Jpeg {
+        quality: i32,
+        progressive: bool,
+        strip: bool,
+        subsample: ForeignSubsample,
+    },
+    Png {
+        /// zlib compression level, 0 to 9
+        compression: i32,
+        interlace: bool,
+        /// Quantise to an 8-bit palette
+        palette: bool,
+        strip: bool,
+    },
+    Webp {
+        quality: i32,
+        lossless: bool,
+        /// CPU effort, 0 to 6
+        effort: i32,
+        strip: bool,
+    },
+    Avif {
+        quality: i32,
+        lossless: bool,
+        /// CPU effort, 0 to 9
+        effort: i32,
+        strip: bool,
+    },
+    Heif {
+        quality: i32,
+        lossless: bool,
+        strip: bool,
+    },
+    Jxl {
+        quality: i32,
+        lossless: bool,
+        /// CPU effort, 1 to 9
+        effort: i32,
+        strip: bool,
+    },
+    Gif {
+        /// CPU effort, 1 to 10
+        effort: i32,
+        strip: bool,
+    },
+    Tiff {
+        compression: ForeignTiffCompression,
+        /// Used by JPEG and WebP compression
+        quality: i32,
+        tile: bool,
+        pyramid: bool,
+        strip: bool,
+    },
+}
+
+impl From<OutputFormat> for Encoder {
+    fn from(format: OutputFormat) -> Encoder {
+        match format {
+            OutputFormat::Jpeg => Encoder::Jpeg {
+                quality: 75,
+                progressive: false,
+                strip: false,
+                subsample: ForeignSubsample::Auto,
+            },
+            OutputFormat::Png => Encoder::Png {
+                compression: 6,
+                interlace: false,
+                palette: false,
+                strip: false,
+            },
+            OutputFormat::Webp => Encoder::Webp {
+                quality: 75,
+                lossless: false,
+                effort: 4,
+                strip: false,
+            },
+            OutputFormat::Avif => Encoder::Avif {
+                quality: 50,
+                lossless: false,
+                effort: 4,
+                strip: false,
+            },
+            OutputFormat::Heif => Encoder::Heif {
+                quality: 50,
+                lossless: false,
+                strip: false,
+            },
+            OutputFormat::Jxl => Encoder::Jxl {
+                quality: 75,
+                lossless: false,
+                effort: 7,
+                strip: false,
+            },
+            OutputFormat::Gif => Encoder::Gif {
+                effort: 7,
+                strip: false,
+            },
+            OutputFormat::Tiff => Encoder::Tiff {
+                compression: ForeignTiffCompression::None,
+                quality: 75,
+                tile: false,
+                pyramid: false,
+                strip: false,
+            },
+        }
+    }
+}
+
+impl Encoder {
+    /// The saver defaults for a format, with the given quality applied
+    pub fn new(format: OutputFormat, quality: Quality) -> Encoder {
+        Encoder::from(format).with_quality(quality)
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        match self {
+            Encoder::Jpeg {
+                ..
+            } => OutputFormat::Jpeg,
+            Encoder::Png {
+                ..
+            } => OutputFormat::Png,
+            Encoder::Webp {
+                ..
+            } => OutputFormat::Webp,
+            Encoder::Avif {
+                ..
+            } => OutputFormat::Avif,
+            Encoder::Heif {
+                ..
+            } => OutputFormat::Heif,
+            Encoder::Jxl {
+                ..
+            } => OutputFormat::Jxl,
+            Encoder::Gif {
+                ..
+            } => OutputFormat::Gif,
+            Encoder::Tiff {
+                ..
+            } => OutputFormat::Tiff,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        self.format()
+            .mime_type()
+    }
+
+    /// Sets the Q factor or lossless mode. PNG and GIF ignore it; JPEG treats lossless as Q 100.
+    pub fn with_quality(mut self, value: Quality) -> Encoder {
+        match &mut self {
+            Encoder::Jpeg {
+                quality,
+                ..
+            }
+            | Encoder::Tiff {
+                quality,
+                ..
+            } => match value {
+                Quality::Default => {}
+                Quality::Lossless => *quality = 100,
+                Quality::Value(q) => *quality = q,
+            },
+            Encoder::Webp {
+                quality,
+                lossless,
+                ..
+            }
+            | Encoder::Avif {
+                quality,
+                lossless,
+                ..
+            }
+            | Encoder::Heif {
+                quality,
+                lossless,
+                ..
+            }
+            | Encoder::Jxl {
+                quality,
+                lossless,
+                ..
+            } => match value {
+                Quality::Default => {}
+                Quality::Lossless => *lossless = true,
+                Quality::Value(q) => *quality = q,
+            },
+            Encoder::Png {
+                ..
+            }
+            | Encoder::Gif {
+                ..
+            } => {}
+        }
+        self
+    }
+
+    /// The same encoder in lossy mode at the given Q, or None for formats without a Q factor
+    fn lossy(mut self, value: i32) -> Option<Encoder> {
+        match &mut self {
+            Encoder::Jpeg {
+                quality,
+                ..
+            } => *quality = value,
+            Encoder::Tiff {
+                compression,
+                quality,
+                ..
+            } => {
+                if !matches!(
+                    compression,
+                    ForeignTiffCompression::Jpeg | ForeignTiffCompression::Webp
+                ) {
+                    return None;
+                }
+                *quality = value;
+            }
+            Encoder::Webp {
+                quality,
+                lossless,
+                ..
+            }
+            | Encoder::Avif {
+                quality,
+                lossless,
+                ..
+            }
+            | Encoder::Heif {
+                quality,
+                lossless,
+                ..
+            }
+            | Encoder::Jxl {
+                quality,
+                lossless,
+                ..
+            } => {
+                *quality = value;
+                *lossless = false;
+            }
+            Encoder::Png {
+                ..
+            }
+            | Encoder::Gif {
+                ..
+            } => return None,
+        }
+        Some(self)
+    }
+
+    fn options(&self) -> VOption<'static> {
+        let mut option = VOption::new();
+        let strip = match *self {
+            Encoder::Jpeg {
+                quality,
+                progressive,
+                strip,
+                subsample,
+            } => {
+                option.add(
+                    "Q",
+                    v_value!(quality),
+                );
+                option.add(
+                    "interlace",
+                    v_value!(progressive),
+                );
+                option.add(
+                    "subsample_mode",
+                    v_value!(subsample as i32),
+                );
+                strip
+            }
+            Encoder::Png {
+                compression,
+                interlace,
+                palette,
+                strip,
+            } => {
+                option.add(
+                    "compression",
+                    v_value!(compression),
+                );
+                option.add(
+                    "interlace",
+                    v_value!(interlace),
+                );
+                option.add(
+                    "palette",
+                    v_value!(palette),
+                );
+                strip
+            }
+            Encoder::Webp {
+                quality,
+                lossless,
+                effort,
+                strip,
+            }
+            | Encoder::Jxl {
+                quality,
+                lossless,
+                effort,
+                strip,
+            } => {
+                option.add(
+                    "Q",
+                    v_value!(quality),
+                );
+                option.add(
+                    "lossless",
+                    v_value!(lossless),
+                );
+                option.add(
+                    "effort",
+                    v_value!(effort),
+                );
+                strip
+            }
+            Encoder::Avif {
+                quality,
+                lossless,
+                effort,
+                strip,
+            } => {
+                option.add(
+                    "compression",
+                    v_value!(ForeignHeifCompression::Av1 as i32),
+                );
+                option.add(
+                    "Q",
+                    v_value!(quality),
+                );
+                option.add(
+                    "lossless",
+                    v_value!(lossless),
+                );
+                option.add(
+                    "effort",
+                    v_value!(effort),
+                );
+                strip
+            }
+            Encoder::Heif {
+                quality,
+                lossless,
+                strip,
+            } => {
+                option.add(
+                    "compression",
+                    v_value!(ForeignHeifCompression::Hevc as i32),
+                );
+                option.add(
+                    "Q",
+                    v_value!(quality),
+                );
+                option.add(
+                    "lossless",
+                    v_value!(lossless),
+                );
+                strip
+            }
+            Encoder::Gif {
+                effort,
+                strip,
+            } => {
+                option.add(
+                    "effort",
+                    v_value!(effort),
+                );
+                strip
+            }
+            Encoder::Tiff {
+                compression,
+                quality,
+                tile,
+                pyramid,
+                strip,
+            } => {
+                option.add(
+                    "compression",
+                    v_value!(compression as i32),
+                );
+                option.add(
+                    "Q",
+                    v_value!(quality),
+                );
+                option.add(
+                    "tile",
+                    v_value!(tile),
+                );
+                option.add(
+                    "pyramid",
+                    v_value!(pyramid),
+                );
+                strip
+            }
+        };
+        if strip {
+            option.add(
+                "keep",
+                v_value!(ForeignKeep::None as i32),
+            );
+        }
+        option
+    }
+}
+
+impl VipsImage {
+    /// Saves to a file with the given encoder, whatever the file suffix
+    pub fn save(&self, filename: &str, encoder: &Encoder) -> Result<()> {
+        let option = encoder.options();
+        match encoder.format() {
+            OutputFormat::Jpeg => self.jpegsave_with_opts(
+                filename,
+                option,
+            ),
+            OutputFormat::Png => self.pngsave_with_opts(
+                filename,
+                option,
+            ),
+            OutputFormat::Webp => self.webpsave_with_opts(
+                filename,
+                option,
+            ),
+            OutputFormat::Avif | OutputFormat::Heif => self.heifsave_with_opts(
+                filename,
+                option,
+            ),
+            OutputFormat::Jxl => self.jxlsave_with_opts(
+                filename,
+                option,
+            ),
+            OutputFormat::Gif => self.gifsave_with_opts(
+                filename,
+                option,
+            ),
+            OutputFormat::Tiff => self.tiffsave_with_opts(
+                filename,
+                option,
+            ),
+        }
+    }
+
+    /// Saves to memory with the given encoder
+    pub fn save_buffer(&self, encoder: &Encoder) -> Result<Vec<u8>> {
+        let option = encoder.options();
+        match encoder.format() {
+            OutputFormat::Jpeg => self.jpegsave_buffer_with_opts(option),
+            OutputFormat::Png => self.pngsave_buffer_with_opts(option),
+            OutputFormat::Webp => self.webpsave_buffer_with_opts(option),
+            OutputFormat::Avif | OutputFormat::Heif => self.heifsave_buffer_with_opts(option),
+            OutputFormat::Jxl => self.jxlsave_buffer_with_opts(option),
+            OutputFormat::Gif => self.gifsave_buffer_with_opts(option),
+            OutputFormat::Tiff => self.tiffsave_buffer_with_opts(option),
+        }
+    }
+
+    /// Saves to a target with the given encoder
+    pub fn save_target(&self, target: &VipsTarget, encoder: &Encoder) -> Result<()> {
+        let option = encoder.options();
+        match encoder.format() {
+            OutputFormat::Jpeg => self.jpegsave_target_with_opts(
+                target,
+                option,
+            ),
+            OutputFormat::Png => self.pngsave_target_with_opts(
+                target,
+                option,
+            ),
+            OutputFormat::Webp => self.webpsave_target_with_opts(
+                target,
+                option,
+            ),
+            OutputFormat::Avif | OutputFormat::Heif => self.heifsave_target_with_opts(
+                target,
+                option,
+            ),
+            OutputFormat::Jxl => self.jxlsave_target_with_opts(
+                target,
+                option,
+            ),
+            OutputFormat::Gif => self.gifsave_target_with_opts(
+                target,
+                option,
+            ),
+            OutputFormat::Tiff => self.tiffsave_target_with_opts(
+                target,
+                option,
+            ),
+        }
+    }
+
+    /// Encodes the image in memory, returning the bytes and their MIME type
+    pub fn encode(
+        &self,
+        format: OutputFormat,
+        quality: Quality,
+    ) -> Result<(
+        Vec<u8>,
+        &'static str,
+    )> {
+        let bytes = self.save_buffer(
+            &Encoder::new(
+                format,
+                quality,
+            ),
+        )?;
+        Ok((
+            bytes,
+            format.mime_type(),
+        ))
+    }
+
+    /// Encodes at the highest Q within `quality` whose output fits in `max_bytes`, returning the
+    /// bytes and the Q used. The image is rendered to memory once and then encoded repeatedly
+    /// in a binary search, so the pipeline is not evaluated again for every attempt.
+    pub fn encode_to_size(
+        &self,
+        encoder: impl Into<Encoder>,
+        max_bytes: usize,
+        quality: RangeInclusive<i32>,
+    ) -> Result<(
+        Vec<u8>,
+        i32,
+    )> {
+        let encoder = encoder.into();
+        if encoder
+            .lossy(*quality.start())
+            .is_none()
+        {
+            return Err(Error::OperationError("Format has no quality setting"));
+        }
+        if quality.is_empty() {
+            return Err(Error::OperationError("Empty quality range"));
+        }
+
+        // render once, so each quality tried only encodes
+        let memory = VipsImage::image_copy_memory(self.copy()?)?;
+
+        let mut low = *quality.start();
+        let mut high = *quality.end();
+        let mut best = None;
+        let mut smallest = usize::MAX;
+        while low <= high {
+            let q = low + (high - low) / 2;
+            let bytes = memory.save_buffer(
+                &encoder
+                    .lossy(q)
+                    .unwrap(),
+            )?;
+            smallest = smallest.min(bytes.len());
+            if bytes.len() <= max_bytes {
+                best = Some((bytes, q));
+                low = q + 1;
+            } else {
+                high = q - 1;
+            }
+        }
+
+        best.ok_or_else(|| {
+            Error::OperationErrorExt(format!(
+                "Cannot encode within {} bytes, the smallest output was {} bytes",
+                max_bytes, smallest
+            ))
+        })
+    }
+}
@@ -0,0 +1,435 @@
+//! Image formats
+//!
+//! libvips picks a loader by sniffing the first bytes of a file, buffer or source. The functions
+//! here expose that choice without decoding any pixels, and list the loaders and savers the local
+//! libvips was built with.
+use crate::{
+    bindings::{self, GType, VipsForeignClass, VipsOperationClass},
+    error::Error,
+    utils, Result, Vips, VipsImage, VipsSource,
+};
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::io::Read;
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+
+/// An image file format, as identified by the loader libvips would use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Heif,
+    Avif,
+    Jxl,
+    Tiff,
+    Gif,
+    Svg,
+    Pdf,
+    Jp2k,
+    /// The native libvips `.v` format
+    Vips,
+    Ppm,
+    Rad,
+    Fits,
+    OpenExr,
+    /// Anything loaded through ImageMagick
+    Magick,
+    /// Any other loader, e.g. `csvload` or `matrixload`
+    Other,
+}
+
+impl ImageFormat {
+    /// Maps a loader or saver nickname such as `jpegload_buffer` or `webpsave` to its format
+    pub fn from_nickname(nickname: &str) -> ImageFormat {
+        let name = nickname
+            .split_once("load")
+            .or_else(|| nickname.split_once("save"))
+            .map_or(
+                nickname,
+                |(name, _)| name,
+            );
+        match name {
+            "jpeg" => ImageFormat::Jpeg,
+            "png" | "spng" => ImageFormat::Png,
+            "webp" => ImageFormat::Webp,
+            "heif" => ImageFormat::Heif,
+            "jxl" => ImageFormat::Jxl,
+            "tiff" => ImageFormat::Tiff,
+            "gif" | "cgif" => ImageFormat::Gif,
+            "svg" => ImageFormat::Svg,
+            "pdf" => ImageFormat::Pdf,
+            "jp2k" => ImageFormat::Jp2k,
+            "vips" => ImageFormat::Vips,
+            "ppm" => ImageFormat::Ppm,
+            "rad" => ImageFormat::Rad,
+            "fits" => ImageFormat::Fits,
+            "openexr" => ImageFormat::OpenExr,
+            "magick" | "magick7" | "magick6" => ImageFormat::Magick,
+            _ => ImageFormat::Other,
+        }
+    }
+
+    /// Maps a file suffix such as `.jpg` or `avif` to its format
+    pub fn from_suffix(suffix: &str) -> ImageFormat {
+        match suffix
+            .trim_start_matches('.')
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "jpg" | "jpeg" | "jpe" | "jfif" => ImageFormat::Jpeg,
+            "png" => ImageFormat::Png,
+            "webp" => ImageFormat::Webp,
+            "heic" | "heif" => ImageFormat::Heif,
+            "avif" => ImageFormat::Avif,
+            "jxl" => ImageFormat::Jxl,
+            "tif" | "tiff" => ImageFormat::Tiff,
+            "gif" => ImageFormat::Gif,
+            "svg" | "svgz" => ImageFormat::Svg,
+            "pdf" => ImageFormat::Pdf,
+            "jp2" | "j2k" | "jpx" | "j2c" | "jpc" => ImageFormat::Jp2k,
+            "v" | "vips" => ImageFormat::Vips,
+            "ppm" | "pgm" | "pbm" | "pfm" | "pnm" => ImageFormat::Ppm,
+            "hdr" => ImageFormat::Rad,
+            "fits" | "fit" | "fts" => ImageFormat::Fits,
+            "exr" => ImageFormat::OpenExr,
+            _ => ImageFormat::Other,
+        }
+    }
+
+    /// The MIME type, e.g. `image/jpeg`
+    pub fn mime_type(&self) -> Option<&'static str> {
+        match self {
+            ImageFormat::Jpeg => Some("image/jpeg"),
+            ImageFormat::Png => Some("image/png"),
+            ImageFormat::Webp => Some("image/webp"),
+            ImageFormat::Heif => Some("image/heif"),
+            ImageFormat::Avif => Some("image/avif"),
+            ImageFormat::Jxl => Some("image/jxl"),
+            ImageFormat::Tiff => Some("image/tiff"),
+            ImageFormat::Gif => Some("image/gif"),
+            ImageFormat::Svg => Some("image/svg+xml"),
+            ImageFormat::Pdf => Some("application/pdf"),
+            ImageFormat::Jp2k => Some("image/jp2"),
+            ImageFormat::Ppm => Some("image/x-portable-pixmap"),
+            ImageFormat::Rad => Some("image/vnd.radiance"),
+            ImageFormat::Fits => Some("image/fits"),
+            ImageFormat::OpenExr => Some("image/x-exr"),
+            ImageFormat::Vips | ImageFormat::Magick | ImageFormat::Other => None,
+        }
+    }
+
+    /// The usual file suffix, including the dot, e.g. `.jpg`
+    pub fn suffix(&self) -> Option<&'static str> {
+        match self {
+            ImageFormat::Jpeg => Some(".jpg"),
+            ImageFormat::Png => Some(".png"),
+            ImageFormat::Webp => Some(".webp"),
+            ImageFormat::Heif => Some(".heic"),
+            ImageFormat::Avif => Some(".avif"),
+            ImageFormat::Jxl => Some(".jxl"),
+            ImageFormat::Tiff => Some(".tif"),
+            ImageFormat::Gif => Some(".gif"),
+            ImageFormat::Svg => Some(".svg"),
+            ImageFormat::Pdf => Some(".pdf"),
+            ImageFormat::Jp2k => Some(".jp2"),
+            ImageFormat::Vips => Some(".v"),
+            ImageFormat::Ppm => Some(".ppm"),
+            ImageFormat::Rad => Some(".hdr"),
+            ImageFormat::Fits => Some(".fits"),
+            ImageFormat::OpenExr => Some(".exr"),
+            ImageFormat::Magick | ImageFormat::Other => None,
+        }
+    }
+}
+
+/// The loader libvips picked for some data, and the format it reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedFormat {
+    /// The loader nickname, e.g. `jpegload_buffer`
+    pub loader: String,
+    pub format: ImageFormat,
+}
+
+impl DetectedFormat {
+    fn new(loader: *const c_char, header: &[u8]) -> Result<DetectedFormat> {
+        if loader.is_null() {
+            unsafe { bindings::vips_error_clear() };
+            return Err(Error::IOError("Unsupported image format"));
+        }
+        let loader = utils::c_string_lossy(loader);
+        let mut format = ImageFormat::from_nickname(&loader);
+        // heifload reads both, the ftyp brand tells them apart
+        if format == ImageFormat::Heif && is_avif(header) {
+            format = ImageFormat::Avif;
+        }
+        Ok(
+            DetectedFormat {
+                loader,
+                format,
+            },
+        )
+    }
+}
+
+fn is_avif(header: &[u8]) -> bool {
+    matches!(
+        header.get(4..12),
+        Some(b"ftypavif") | Some(b"ftypavis")
+    )
+}
+
+impl VipsImage {
+    /// Finds the loader for an encoded image without decoding it
+    pub fn detect_format(buffer: &[u8]) -> Result<DetectedFormat> {
+        let loader = unsafe {
+            bindings::vips_foreign_find_load_buffer(
+                buffer.as_ptr() as *const c_void,
+                buffer.len() as u64,
+            )
+        };
+        DetectedFormat::new(
+            loader,
+            buffer,
+        )
+    }
+
+    /// Finds the loader for a file without decoding it
+    pub fn detect_file_format(filename: &str) -> Result<DetectedFormat> {
+        let c_filename = utils::new_c_string(filename)?;
+        let loader = unsafe { bindings::vips_foreign_find_load(c_filename.as_ptr()) };
+        let mut header = Vec::with_capacity(12);
+        if let Ok(file) = std::fs::File::open(filename) {
+            let _ = file
+                .take(12)
+                .read_to_end(&mut header);
+        }
+        DetectedFormat::new(
+            loader,
+            &header,
+        )
+    }
+}
+
+impl VipsSource {
+    /// Finds the loader for the source without decoding it. Only the first bytes are read.
+    pub fn detect_format(&self) -> Result<DetectedFormat> {
+        unsafe {
+            let loader = bindings::vips_foreign_find_load_source(self.ctx);
+            let mut data: *mut u8 = std::ptr::null_mut();
+            let length = bindings::vips_source_sniff_at_most(
+                self.ctx,
+                &mut data,
+                12,
+            );
+            let header = if data.is_null() || length <= 0 {
+                &[]
+            } else {
+                std::slice::from_raw_parts(
+                    data,
+                    length as usize,
+                )
+            };
+            DetectedFormat::new(
+                loader,
+                header,
+            )
+        }
+    }
+}
+
+/// A loader or saver family in the local libvips, e.g. `jpegsave` with its `_buffer` and `_target` variants
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignSupport {
+    /// The nickname of the family, e.g. `jpegsave` or `heifload`
+    pub name: String,
+    /// The formats it handles, from its suffixes. `heifsave` writes both HEIF and AVIF.
+    pub formats: Vec<ImageFormat>,
+    /// File suffixes, including the dot
+    pub suffixes: Vec<String>,
+    /// Whether a file variant exists, e.g. `jpegsave`
+    pub file: bool,
+    /// Whether a buffer variant exists, e.g. `jpegsave_buffer`
+    pub buffer: bool,
+    /// Whether a stream variant exists: `_source` for loaders, `_target` for savers
+    pub stream: bool,
+}
+
+impl ForeignSupport {
+    /// The MIME types of the formats it handles
+    pub fn mime_types(&self) -> Vec<&'static str> {
+        self.formats
+            .iter()
+            .filter_map(|format| format.mime_type())
+            .collect()
+    }
+
+    pub fn supports(&self, format: ImageFormat) -> bool {
+        self.formats
+            .contains(&format)
+    }
+}
+
+impl Vips {
+    /// The savers libvips was built with
+    pub fn supported_savers() -> Vec<ForeignSupport> {
+        foreign_support(
+            unsafe { bindings::vips_foreign_save_get_type() },
+            "_target",
+        )
+    }
+
+    /// The loaders libvips was built with
+    pub fn supported_loaders() -> Vec<ForeignSupport> {
+        foreign_support(
+            unsafe { bindings::vips_foreign_load_get_type() },
+            "_source",
+        )
+    }
+
+    /// Every suffix some saver accepts, e.g. `.jpg`
+    pub fn supported_suffixes() -> Vec<String> {
+        unsafe {
+            let suffixes = bindings::vips_foreign_get_suffixes();
+            if suffixes.is_null() {
+                return Vec::new();
+            }
+            let mut result = Vec::new();
+            let mut i = 0;
+            while !(*suffixes.add(i)).is_null() {
+                result.push(utils::c_string_lossy(*suffixes.add(i)));
+                i += 1;
+            }
+            bindings::g_strfreev(suffixes);
+            result
+        }
+    }
+}
+
+struct ForeignClass {
+    nickname: String,
+    suffixes: Vec<String>,
+}
+
+fn foreign_support(base_type: GType, stream_suffix: &str) -> Vec<ForeignSupport> {
+    let mut classes: Vec<ForeignClass> = Vec::new();
+    unsafe {
+        bindings::vips_type_map_all(
+            base_type,
+            Some(collect_foreign),
+            &mut classes as *mut Vec<ForeignClass> as *mut c_void,
+        );
+    }
+
+    let mut families: BTreeMap<String, ForeignSupport> = BTreeMap::new();
+    for class in classes {
+        let (name, variant) = match class
+            .nickname
+            .split_once('_')
+        {
+            Some((name, variant)) => (
+                name.to_string(),
+                format!(
+                    "_{}",
+                    variant
+                ),
+            ),
+            None => (
+                class
+                    .nickname
+                    .clone(),
+                String::new(),
+            ),
+        };
+        let family = families
+            .entry(name.clone())
+            .or_insert_with(
+                || ForeignSupport {
+                    name,
+                    formats: Vec::new(),
+                    suffixes: Vec::new(),
+                    file: false,
+                    buffer: false,
+                    stream: false,
+                },
+            );
+        match variant.as_str() {
+            "" => family.file = true,
+            "_buffer" => family.buffer = true,
+            v if v == stream_suffix => family.stream = true,
+            _ => {}
+        }
+        for suffix in class.suffixes {
+            if !family
+                .suffixes
+                .contains(&suffix)
+            {
+                family
+                    .suffixes
+                    .push(suffix);
+            }
+        }
+    }
+
+    families
+        .into_values()
+        .map(
+            |mut family| {
+                for suffix in &family.suffixes {
+                    let format = ImageFormat::from_suffix(suffix);
+                    if format != ImageFormat::Other && !family.supports(format) {
+                        family
+                            .formats
+                            .push(format);
+                    }
+                }
+                if family
+                    .formats
+                    .is_empty()
+                {
+                    family
+                        .formats
+                        .push(ImageFormat::from_nickname(&family.name));
+                }
+                family
+            },
+        )
+        .collect()
+}
+
+unsafe extern "C" fn collect_foreign(type_: GType, a: *mut c_void) -> *mut c_void {
+    if bindings::g_type_test_flags(
+        type_,
+        bindings::GTypeFlags_G_TYPE_FLAG_ABSTRACT,
+    ) != 0
+    {
+        return null_mut();
+    }
+
+    let class = bindings::g_type_class_ref(type_) as *mut VipsForeignClass;
+    let operation_class = class as *mut VipsOperationClass;
+    let deprecated =
+        (*operation_class).flags & bindings::VipsOperationFlags_VIPS_OPERATION_DEPRECATED != 0;
+    let mut suffixes = Vec::new();
+    let mut suffs = (*class).suffs;
+    if !suffs.is_null() {
+        while !(*suffs).is_null() {
+            suffixes.push(utils::c_string_lossy(*suffs));
+            suffs = suffs.add(1);
+        }
+    }
+    bindings::g_type_class_unref(class as _);
+
+    let nickname = bindings::vips_nickname_find(type_);
+    if !deprecated && !nickname.is_null() {
+        let classes = &mut *(a as *mut Vec<ForeignClass>);
+        classes.push(
+            ForeignClass {
+                nickname: utils::c_string_lossy(nickname),
+                suffixes,
+            },
+        );
+    }
+    null_mut()
+}
@@ -0,0 +1,153 @@
+//! Perceptual hashes
+//!
+//! Each hash shrinks the image to a few pixels of luminance and packs one bit per pixel or
+//! frequency into a `u64`, most significant bit first in row-major order. Similar images give
+//! hashes a small [`hamming_distance`] apart; 10 or less out of 64 usually means a duplicate.
+use crate::{
+    ops::{BandFormat, Interpretation, Size},
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+use std::f64::consts::PI;
+
+/// The side of the image the DCT of `phash` is taken over
+const PHASH_SIZE: usize = 32;
+
+/// The number of bits that differ between two hashes
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+impl VipsImage {
+    /// Average hash: each bit tells whether a pixel of an 8x8 thumbnail is brighter than the mean
+    pub fn ahash(&self) -> Result<u64> {
+        let pixels = self.luminance_pixels(8, 8)?;
+        let mean = pixels
+            .iter()
+            .sum::<f64>()
+            / pixels.len() as f64;
+        Ok(pack_bits(
+            pixels
+                .iter()
+                .map(|&p| p > mean),
+        ))
+    }
+
+    /// Difference hash: each bit tells whether a pixel of a 9x8 thumbnail is darker than its right neighbour
+    pub fn dhash(&self) -> Result<u64> {
+        let pixels = self.luminance_pixels(9, 8)?;
+        Ok(pack_bits(
+            pixels
+                .chunks_exact(9)
+                .flat_map(|row| {
+                    row.windows(2)
+                        .map(|pair| pair[0] < pair[1])
+                }),
+        ))
+    }
+
+    /// DCT hash: each bit tells whether one of the 8x8 lowest frequencies of a 32x32 thumbnail is
+    /// above their median, which survives rescaling, recompression and small colour changes
+    pub fn phash(&self) -> Result<u64> {
+        let pixels = self.luminance_pixels(
+            PHASH_SIZE as i32,
+            PHASH_SIZE as i32,
+        )?;
+        let dct = dct_2d(&pixels);
+        let low: Vec<f64> = (0..8)
+            .flat_map(|v| (0..8).map(move |u| (u, v)))
+            .map(|(u, v)| dct[v * PHASH_SIZE + u])
+            .collect();
+        // the DC term is the overall brightness, so leave it out of the median
+        let mut sorted = low[1..].to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = sorted[sorted.len() / 2];
+        Ok(pack_bits(
+            low.iter()
+                .map(|&c| c > median),
+        ))
+    }
+
+    /// Luminance of a `width` x `height` thumbnail, ignoring the aspect ratio, in row-major order
+    fn luminance_pixels(&self, width: i32, height: i32) -> Result<Vec<f64>> {
+        let mut small = self.thumbnail_image_with_opts(
+            width,
+            VOption::new()
+                .set(
+                    "height",
+                    v_value!(height),
+                )
+                .set(
+                    "size",
+                    v_value!(Size::Force as i32),
+                ),
+        )?;
+        if small.image_hasalpha() {
+            small = small.flatten()?;
+        }
+        let grey = small
+            .colourspace(Interpretation::BW)?
+            .extract_band(0)?
+            .cast(BandFormat::Float)?;
+        Ok(grey
+            .image_write_to_memory()
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect())
+    }
+}
+
+fn pack_bits(bits: impl Iterator<Item = bool>) -> u64 {
+    bits.fold(
+        0,
+        |hash, bit| hash << 1 | bit as u64,
+    )
+}
+
+/// Type-II DCT of a square image, rows then columns
+fn dct_2d(pixels: &[f64]) -> Vec<f64> {
+    let n = PHASH_SIZE;
+    let cosines: Vec<f64> = (0..n * n)
+        .map(|i| {
+            let (k, x) = (
+                i / n,
+                i % n,
+            );
+            (PI / n as f64 * (x as f64 + 0.5) * k as f64).cos()
+        })
+        .collect();
+    let dct_1d = |input: &mut dyn Iterator<Item = f64>| -> Vec<f64> {
+        let input: Vec<f64> = input.collect();
+        (0..n)
+            .map(|k| {
+                input
+                    .iter()
+                    .enumerate()
+                    .map(|(x, value)| value * cosines[k * n + x])
+                    .sum()
+            })
+            .collect()
+    };
+
+    let mut rows = vec![0.0; n * n];
+    for y in 0..n {
+        let row = dct_1d(
+            &mut pixels[y * n..(y + 1) * n]
+                .iter()
+                .copied(),
+        );
+        rows[y * n..(y + 1) * n].copy_from_slice(&row);
+    }
+    let mut result = vec![0.0; n * n];
+    for x in 0..n {
+        let column = dct_1d(&mut (0..n).map(|y| rows[y * n + x]));
+        for (y, value) in column
+            .into_iter()
+            .enumerate()
+        {
+            result[y * n + x] = value;
+        }
+    }
+    result
+}
@@ -0,0 +1,352 @@
+//! ICC profiles
+//!
+//! [`IccProfile`] holds the bytes of a profile and reads what it needs from the ICC header and
+//! the `desc` tag. Images carry their profile in the `icc-profile-data` field.
+use crate::{error::Error, ops::Intent, v_value, voption::VOption, Result, VipsImage};
+use std::{
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const ICC_NAME: &str = "icc-profile-data";
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The data colour space from the ICC header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IccColourSpace {
+    Rgb,
+    Cmyk,
+    Gray,
+    Lab,
+    Xyz,
+    /// Any other signature, e.g. `YCbr`
+    Other(String),
+}
+
+/// An ICC profile
+#[derive(Debug, Clone)]
+pub struct IccProfile {
+    data: Vec<u8>,
+    // a built-in name or file path libvips can load the profile from directly
+    location: Option<String>,
+}
+
+impl IccProfile {
+    pub fn from_bytes(data: &[u8]) -> Result<IccProfile> {
+        Self::check(data)?;
+        Ok(
+            IccProfile {
+                data: data.to_vec(),
+                location: None,
+            },
+        )
+    }
+
+    pub fn from_file(filename: &str) -> Result<IccProfile> {
+        let data =
+            std::fs::read(filename).map_err(|_| Error::IOError("Cannot read ICC profile file"))?;
+        Self::check(&data)?;
+        Ok(
+            IccProfile {
+                data,
+                location: Some(filename.to_string()),
+            },
+        )
+    }
+
+    /// Loads one of the profiles built into libvips: `srgb`, `p3` or `cmyk`
+    pub fn builtin(name: &str) -> Result<IccProfile> {
+        let data = VipsImage::profile_load(name)?;
+        Self::check(&data)?;
+        Ok(
+            IccProfile {
+                data,
+                location: Some(name.to_string()),
+            },
+        )
+    }
+
+    fn check(data: &[u8]) -> Result<()> {
+        if data.len() < 132 || &data[36..40] != b"acsp" {
+            Err(Error::IOError("Not an ICC profile"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The profile version as (major, minor, bugfix), e.g. (4, 3, 0)
+    pub fn version(&self) -> (u8, u8, u8) {
+        (
+            self.data[8],
+            self.data[9] >> 4,
+            self.data[9] & 0x0f,
+        )
+    }
+
+    pub fn colour_space(&self) -> IccColourSpace {
+        match &self.data[16..20] {
+            b"RGB " => IccColourSpace::Rgb,
+            b"CMYK" => IccColourSpace::Cmyk,
+            b"GRAY" => IccColourSpace::Gray,
+            b"Lab " => IccColourSpace::Lab,
+            b"XYZ " => IccColourSpace::Xyz,
+            other => IccColourSpace::Other(
+                String::from_utf8_lossy(other)
+                    .trim_end()
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// The device class signature, e.g. `mntr` for displays or `prtr` for printers
+    pub fn device_class(&self) -> String {
+        String::from_utf8_lossy(&self.data[12..16]).to_string()
+    }
+
+    /// The profile description from the `desc` tag, e.g. `sRGB IEC61966-2.1`
+    pub fn description(&self) -> Option<String> {
+        let tag = self.tag(b"desc")?;
+        match tag.get(0..4)? {
+            // textDescriptionType (v2): count, then ASCII
+            b"desc" => {
+                let count = read_u32(tag, 8)? as usize;
+                let text = tag.get(12..count.checked_add(12)?)?;
+                Some(
+                    String::from_utf8_lossy(text)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                )
+            }
+            // multiLocalizedUnicodeType (v4): the first record, UTF-16BE
+            b"mluc" => {
+                if read_u32(tag, 8)? == 0 {
+                    return None;
+                }
+                let length = read_u32(tag, 20)? as usize;
+                let offset = read_u32(tag, 24)? as usize;
+                let text = tag.get(offset..offset.checked_add(length)?)?;
+                let units: Vec<u16> = text
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                Some(
+                    String::from_utf16_lossy(&units)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn tag(&self, signature: &[u8; 4]) -> Option<&[u8]> {
+        // the count comes from the file, so it is limited to the entries which fit in it
+        let count = (read_u32(
+            &self.data,
+            128,
+        )? as usize)
+            .min(
+                (self
+                    .data
+                    .len()
+                    - 132)
+                    / 12,
+            );
+        (0..count).find_map(|i| {
+            let entry = 132 + i * 12;
+            if self
+                .data
+                .get(entry..entry + 4)?
+                != signature
+            {
+                return None;
+            }
+            let offset = read_u32(
+                &self.data,
+                entry + 4,
+            )? as usize;
+            let size = read_u32(
+                &self.data,
+                entry + 8,
+            )? as usize;
+            self.data
+                .get(offset..offset.checked_add(size)?)
+        })
+    }
+}
+
+impl PartialEq for IccProfile {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// A profile written out for libvips, which loads transform profiles by name or filename
+struct TempProfile {
+    path: std::path::PathBuf,
+}
+
+impl TempProfile {
+    /// Creates a new file with a name no other call uses, so an existing file is never
+    /// overwritten. The guard exists before the write, so a failed write is removed too.
+    fn new(profile: &IccProfile) -> Result<TempProfile> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(
+                0,
+                |elapsed| elapsed.subsec_nanos(),
+            );
+        for _ in 0..16 {
+            let path = std::env::temp_dir().join(format!(
+                "libvips-rs-{}-{}-{}.icc",
+                std::process::id(),
+                nanos,
+                TEMP_COUNTER.fetch_add(
+                    1,
+                    Ordering::Relaxed
+                )
+            ));
+            let mut file = match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => file,
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(_) => return Err(Error::IOError("Cannot write temporary ICC profile")),
+            };
+            let temp = TempProfile {
+                path,
+            };
+            file.write_all(&profile.data)
+                .map_err(|_| Error::IOError("Cannot write temporary ICC profile"))?;
+            return Ok(temp);
+        }
+        Err(Error::IOError("Cannot write temporary ICC profile"))
+    }
+}
+
+impl Drop for TempProfile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl VipsImage {
+    /// The embedded ICC profile, if any
+    pub fn icc_profile(&self) -> Option<IccProfile> {
+        let data = self
+            .get_blob(ICC_NAME)
+            .ok()?;
+        IccProfile::from_bytes(&data).ok()
+    }
+
+    /// Embeds the profile without changing the pixels
+    pub fn set_icc_profile(&self, profile: &IccProfile) {
+        self.set_blob(
+            ICC_NAME,
+            &profile.data,
+        );
+    }
+
+    /// Removes the embedded profile without changing the pixels
+    pub fn remove_icc_profile(&self) -> bool {
+        self.remove(ICC_NAME)
+    }
+
+    /// Transforms the pixels from the embedded profile, or sRGB if there is none, to the given
+    /// profile, and embeds it in the result.
+    ///
+    /// libvips only loads transform profiles by name or from files, so a profile from
+    /// `IccProfile::from_bytes` or `icc_profile` is written to a new file in
+    /// `std::env::temp_dir()` for the duration of the call. The file is removed afterwards
+    /// whether or not the transform succeeds.
+    pub fn convert_to_profile(&self, profile: &IccProfile, intent: Intent) -> Result<VipsImage> {
+        let temp;
+        let output_profile = match &profile.location {
+            Some(location) => location.as_str(),
+            None => {
+                temp = TempProfile::new(profile)?;
+                temp.path
+                    .to_str()
+                    .ok_or(Error::IOError("Invalid temporary path"))?
+            }
+        };
+        self.icc_transform_with_opts(
+            output_profile,
+            VOption::new()
+                .set(
+                    "embedded",
+                    v_value!(true),
+                )
+                .set(
+                    "intent",
+                    v_value!(intent as i32),
+                ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A profile header with the given tag count and table
+    fn profile(count: u32, table: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&count.to_be_bytes());
+        data.extend_from_slice(table);
+        data
+    }
+
+    #[test]
+    fn tag_count_is_limited_to_the_data() {
+        let data = profile(
+            u32::MAX,
+            &[],
+        );
+        let profile = IccProfile::from_bytes(&data).unwrap();
+        assert_eq!(
+            profile.description(),
+            None
+        );
+    }
+
+    #[test]
+    fn description_is_read_from_a_v2_tag() {
+        let mut table = Vec::new();
+        table.extend_from_slice(b"desc");
+        table.extend_from_slice(&144u32.to_be_bytes());
+        table.extend_from_slice(&17u32.to_be_bytes());
+        let mut data = profile(1, &table);
+        data.extend_from_slice(b"desc\0\0\0\0");
+        data.extend_from_slice(&5u32.to_be_bytes());
+        data.extend_from_slice(b"Test\0");
+        assert_eq!(
+            IccProfile::from_bytes(&data)
+                .unwrap()
+                .description(),
+            Some("Test".to_string())
+        );
+
+        // a size past the end of the data is not read
+        data[140..144].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            IccProfile::from_bytes(&data)
+                .unwrap()
+                .description(),
+            None
+        );
+    }
+}
@@ -0,0 +1,601 @@
+//! IIIF Image API requests
+//!
+//! [`IiifRequest`] parses the `{region}/{size}/{rotation}/{quality}.{format}` part of an
+//! [IIIF Image API 3](https://iiif.io/api/image/3.0/) URL and renders it from a source image:
+//!
+//! ```no_run
+//! use libvips::{iiif::IiifRequest, VipsImage};
+//!
+//! let request: IiifRequest = "0,0,1024,1024/512,/0/default.jpg".parse().unwrap();
+//! let (bytes, mime_type) = request.render_file("scan.tif").unwrap();
+//! ```
+//!
+//! The version 2 size `full` is accepted as `max`.
+use crate::{
+    encode::{Encoder, OutputFormat},
+    error::Error,
+    format::ImageFormat,
+    ops::{Angle, Direction, Interpretation, OperationRelational},
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+use std::str::FromStr;
+
+const BAD_REGION: &str = "Bad IIIF region";
+const BAD_SIZE: &str = "Bad IIIF size";
+
+/// The part of the image to return
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Full,
+    /// The largest centred square
+    Square,
+    /// `x,y,w,h` in pixels
+    Pixels {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
+    /// `pct:x,y,w,h` in percent of the full image
+    Percent {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+}
+
+/// The size the region is scaled to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Size {
+    /// `max`, or `full` in version 2
+    Max,
+    /// `w,`, keeping the aspect ratio
+    Width(i32),
+    /// `,h`, keeping the aspect ratio
+    Height(i32),
+    /// `pct:n`
+    Percent(f64),
+    /// `w,h`, ignoring the aspect ratio
+    Exact(i32, i32),
+    /// `!w,h`, the largest size which fits, keeping the aspect ratio
+    BestFit(i32, i32),
+}
+
+/// The colour of the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    /// As the source
+    Default,
+    Color,
+    Gray,
+    /// Black and white, thresholded at half intensity
+    Bitonal,
+}
+
+/// One parsed image request
+#[derive(Debug, Clone, PartialEq)]
+pub struct IiifRequest {
+    pub region: Region,
+    pub size: Size,
+    /// Whether the size may be larger than the region, the `^` prefix
+    pub upscale: bool,
+    /// Flip horizontally before rotating, the `!` prefix
+    pub mirror: bool,
+    /// Degrees clockwise
+    pub rotation: f64,
+    pub quality: Quality,
+    pub format: OutputFormat,
+}
+
+impl FromStr for IiifRequest {
+    type Err = Error;
+
+    /// Parses `region/size/rotation/quality.format`. Any leading segments, such as the prefix and
+    /// identifier, are ignored.
+    fn from_str(path: &str) -> Result<IiifRequest> {
+        let segments: Vec<&str> = path
+            .trim_matches('/')
+            .split('/')
+            .collect();
+        if segments.len() < 4 {
+            return Err(
+                Error::OperationError("An IIIF request needs region/size/rotation/quality.format"),
+            );
+        }
+        let [region, size, rotation, quality] = segments[segments.len() - 4..] else {
+            unreachable!()
+        };
+        let region = parse_region(region)?;
+        let (size, upscale) = parse_size(size)?;
+        let (rotation, mirror) = parse_rotation(rotation)?;
+        let (quality, format) = quality
+            .rsplit_once('.')
+            .ok_or(Error::OperationError("Missing IIIF format"))?;
+        let quality = match quality {
+            "default" => Quality::Default,
+            "color" => Quality::Color,
+            "gray" => Quality::Gray,
+            "bitonal" => Quality::Bitonal,
+            _ => return Err(Error::OperationError("Bad IIIF quality")),
+        };
+        let format = match format {
+            "jpg" => OutputFormat::Jpeg,
+            "png" => OutputFormat::Png,
+            "webp" => OutputFormat::Webp,
+            "gif" => OutputFormat::Gif,
+            "tif" => OutputFormat::Tiff,
+            "jp2" | "pdf" => return Err(Error::OperationError("Unsupported IIIF format")),
+            _ => return Err(Error::OperationError("Bad IIIF format")),
+        };
+        Ok(
+            IiifRequest {
+                region,
+                size,
+                upscale,
+                mirror,
+                rotation,
+                quality,
+                format,
+            },
+        )
+    }
+}
+
+fn parse_numbers<T: FromStr>(text: &str, count: usize) -> Option<Vec<T>> {
+    let numbers = text
+        .split(',')
+        .map(|item| item.parse::<T>())
+        .collect::<std::result::Result<Vec<T>, _>>()
+        .ok()?;
+    (numbers.len() == count).then_some(numbers)
+}
+
+fn parse_region(text: &str) -> Result<Region> {
+    match text {
+        "full" => Ok(Region::Full),
+        "square" => Ok(Region::Square),
+        _ => {
+            if let Some(percent) = text.strip_prefix("pct:") {
+                let n =
+                    parse_numbers::<f64>(percent, 4).ok_or(Error::OperationError(BAD_REGION))?;
+                if n.iter()
+                    .any(|v| !v.is_finite() || *v < 0.0)
+                    || n[2] <= 0.0
+                    || n[3] <= 0.0
+                {
+                    return Err(Error::OperationError(BAD_REGION));
+                }
+                Ok(
+                    Region::Percent {
+                        x: n[0],
+                        y: n[1],
+                        width: n[2],
+                        height: n[3],
+                    },
+                )
+            } else {
+                let n = parse_numbers::<i32>(text, 4).ok_or(Error::OperationError(BAD_REGION))?;
+                if n[0] < 0 || n[1] < 0 || n[2] <= 0 || n[3] <= 0 {
+                    return Err(Error::OperationError(BAD_REGION));
+                }
+                Ok(
+                    Region::Pixels {
+                        x: n[0],
+                        y: n[1],
+                        width: n[2],
+                        height: n[3],
+                    },
+                )
+            }
+        }
+    }
+}
+
+fn parse_size(text: &str) -> Result<(Size, bool)> {
+    let (text, upscale) = match text.strip_prefix('^') {
+        Some(rest) => (rest, true),
+        None => (
+            text, false,
+        ),
+    };
+    let size = if text == "max" || text == "full" {
+        Size::Max
+    } else if let Some(percent) = text.strip_prefix("pct:") {
+        let n: f64 = percent
+            .parse()
+            .map_err(|_| Error::OperationError(BAD_SIZE))?;
+        if !n.is_finite() || n <= 0.0 {
+            return Err(Error::OperationError(BAD_SIZE));
+        }
+        Size::Percent(n)
+    } else {
+        let (best_fit, text) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (
+                false, text,
+            ),
+        };
+        let (width, height) = text
+            .split_once(',')
+            .ok_or(Error::OperationError(BAD_SIZE))?;
+        let parse = |v: &str| -> Result<Option<i32>> {
+            if v.is_empty() {
+                return Ok(None);
+            }
+            match v.parse::<i32>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err(Error::OperationError(BAD_SIZE)),
+            }
+        };
+        match (
+            best_fit,
+            parse(width)?,
+            parse(height)?,
+        ) {
+            (false, Some(w), None) => Size::Width(w),
+            (false, None, Some(h)) => Size::Height(h),
+            (false, Some(w), Some(h)) => Size::Exact(w, h),
+            (true, Some(w), Some(h)) => Size::BestFit(w, h),
+            _ => return Err(Error::OperationError(BAD_SIZE)),
+        }
+    };
+    Ok((
+        size,
+        upscale,
+    ))
+}
+
+fn parse_rotation(text: &str) -> Result<(f64, bool)> {
+    let (text, mirror) = match text.strip_prefix('!') {
+        Some(rest) => (rest, true),
+        None => (
+            text, false,
+        ),
+    };
+    match text.parse::<f64>() {
+        Ok(degrees) if (0.0..=360.0).contains(&degrees) => Ok((
+            degrees,
+            mirror,
+        )),
+        _ => Err(Error::OperationError("Bad IIIF rotation")),
+    }
+}
+
+impl IiifRequest {
+    /// The region in pixels of an image of this size, clipped to the image
+    pub fn region_rect(
+        &self,
+        width: i32,
+        height: i32,
+    ) -> Result<(
+        i32,
+        i32,
+        i32,
+        i32,
+    )> {
+        let (x, y, w, h) = match self.region {
+            Region::Full => (
+                0,
+                0,
+                width,
+                height,
+            ),
+            Region::Square => {
+                let side = width.min(height);
+                (
+                    (width - side) / 2,
+                    (height - side) / 2,
+                    side,
+                    side,
+                )
+            }
+            Region::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => (
+                x,
+                y,
+                width,
+                height,
+            ),
+            Region::Percent {
+                x,
+                y,
+                width: w,
+                height: h,
+            } => {
+                let scale_x = width as f64 / 100.0;
+                let scale_y = height as f64 / 100.0;
+                (
+                    (x * scale_x).round() as i32,
+                    (y * scale_y).round() as i32,
+                    ((w * scale_x).round() as i32).max(1),
+                    ((h * scale_y).round() as i32).max(1),
+                )
+            }
+        };
+        if x >= width || y >= height {
+            return Err(Error::OperationError("IIIF region is outside the image"));
+        }
+        Ok((
+            x,
+            y,
+            w.min(width - x),
+            h.min(height - y),
+        ))
+    }
+
+    /// The size of the result for a region of this size, before rotation
+    pub fn output_size(&self, width: i32, height: i32) -> Result<(i32, i32)> {
+        let aspect = |w: f64, h: f64| {
+            (
+                (w.round() as i32).max(1),
+                (h.round() as i32).max(1),
+            )
+        };
+        let (w, h) = (
+            width as f64,
+            height as f64,
+        );
+        let (out_w, out_h) = match self.size {
+            Size::Max => (
+                width,
+                height,
+            ),
+            Size::Width(n) => aspect(
+                n as f64,
+                h * n as f64 / w,
+            ),
+            Size::Height(n) => aspect(
+                w * n as f64 / h,
+                n as f64,
+            ),
+            Size::Percent(p) => aspect(
+                w * p / 100.0,
+                h * p / 100.0,
+            ),
+            Size::Exact(a, b) => (a, b),
+            Size::BestFit(a, b) => {
+                let mut scale = (a as f64 / w).min(b as f64 / h);
+                if !self.upscale {
+                    scale = scale.min(1.0);
+                }
+                aspect(
+                    w * scale,
+                    h * scale,
+                )
+            }
+        };
+        if !self.upscale && (out_w > width || out_h > height) {
+            return Err(
+                Error::OperationError("IIIF size is larger than the region, use ^ to upscale"),
+            );
+        }
+        Ok((
+            out_w, out_h,
+        ))
+    }
+
+    /// Renders the request from an image, returning the encoded bytes and their MIME type
+    pub fn render(
+        &self,
+        image: &VipsImage,
+    ) -> Result<(
+        Vec<u8>,
+        &'static str,
+    )> {
+        let (x, y, w, h) = self.region_rect(
+            image.get_width(),
+            image.get_height(),
+        )?;
+        let size = self.output_size(w, h)?;
+        self.render_area(
+            image,
+            (
+                x as f64,
+                y as f64,
+                w as f64,
+                h as f64,
+            ),
+            size,
+        )
+    }
+
+    /// Renders the request from a file. JPEGs are shrunk while they load and pyramidal TIFFs load
+    /// the smallest page which is still large enough, so small tiles of huge images are cheap.
+    pub fn render_file(
+        &self,
+        filename: &str,
+    ) -> Result<(
+        Vec<u8>,
+        &'static str,
+    )> {
+        let full = VipsImage::new_from_file(filename)?;
+        let (width, height) = (
+            full.get_width(),
+            full.get_height(),
+        );
+        let (x, y, w, h) = self.region_rect(
+            width,
+            height,
+        )?;
+        let (out_w, out_h) = self.output_size(w, h)?;
+        let shrink = (w as f64 / out_w as f64).min(h as f64 / out_h as f64);
+
+        let source = match VipsImage::detect_file_format(filename)?.format {
+            ImageFormat::Jpeg if shrink >= 2.0 => {
+                let factor = [8, 4, 2]
+                    .into_iter()
+                    .find(|&f| f as f64 <= shrink)
+                    .unwrap_or(1);
+                VipsImage::jpegload_with_opts(
+                    filename,
+                    VOption::new().set(
+                        "shrink",
+                        v_value!(factor),
+                    ),
+                )?
+            }
+            ImageFormat::Tiff if shrink >= 2.0 => pyramid_page(
+                filename,
+                &full,
+                shrink,
+            )?
+            .unwrap_or(full),
+            _ => full,
+        };
+        // the region in the coordinates of the loaded image
+        let scale_x = source.get_width() as f64 / width as f64;
+        let scale_y = source.get_height() as f64 / height as f64;
+        self.render_area(
+            &source,
+            (
+                x as f64 * scale_x,
+                y as f64 * scale_y,
+                w as f64 * scale_x,
+                h as f64 * scale_y,
+            ),
+            (
+                out_w, out_h,
+            ),
+        )
+    }
+
+    fn render_area(
+        &self,
+        image: &VipsImage,
+        area: (
+            f64,
+            f64,
+            f64,
+            f64,
+        ),
+        (out_w, out_h): (i32, i32),
+    ) -> Result<(
+        Vec<u8>,
+        &'static str,
+    )> {
+        let left = (area
+            .0
+            .floor() as i32)
+            .clamp(
+                0,
+                image.get_width() - 1,
+            );
+        let top = (area
+            .1
+            .floor() as i32)
+            .clamp(
+                0,
+                image.get_height() - 1,
+            );
+        let right = ((area.0 + area.2).ceil() as i32).clamp(
+            left + 1,
+            image.get_width(),
+        );
+        let bottom = ((area.1 + area.3).ceil() as i32).clamp(
+            top + 1,
+            image.get_height(),
+        );
+        let mut out = image.extract_area(
+            left,
+            top,
+            right - left,
+            bottom - top,
+        )?;
+        if out.get_width() != out_w || out.get_height() != out_h {
+            out = out.resize_with_opts(
+                out_w as f64 / out.get_width() as f64,
+                VOption::new().set(
+                    "vscale",
+                    v_value!(out_h as f64 / out.get_height() as f64),
+                ),
+            )?;
+        }
+
+        if self.mirror {
+            out = out.flip(Direction::Horizontal)?;
+        }
+        let angle = self.rotation % 360.0;
+        out = match (angle.fract() == 0.0).then_some(angle as i32) {
+            Some(0) => out,
+            Some(90) => out.rot(Angle::D90)?,
+            Some(180) => out.rot(Angle::D180)?,
+            Some(270) => out.rot(Angle::D270)?,
+            _ => {
+                // formats with alpha get a transparent background outside the rotated image
+                if self.format != OutputFormat::Jpeg && !out.image_hasalpha() {
+                    out = out.bandjoin_const(&[255.0])?;
+                }
+                out.rotate_with_opts(
+                    angle,
+                    VOption::new().set(
+                        "background",
+                        v_value!([0.0].as_slice()),
+                    ),
+                )?
+            }
+        };
+
+        out = match self.quality {
+            Quality::Default => out,
+            Quality::Color => out.colourspace(Interpretation::Srgb)?,
+            Quality::Gray => out.colourspace(Interpretation::BW)?,
+            Quality::Bitonal => {
+                if out.image_hasalpha() {
+                    out = out.flatten()?;
+                }
+                out.colourspace(Interpretation::BW)?
+                    .extract_band(0)?
+                    .relational_const(
+                        OperationRelational::Moreeq,
+                        &[128.0],
+                    )?
+            }
+        };
+        let encoder = Encoder::from(self.format);
+        Ok((
+            out.save_buffer(&encoder)?,
+            encoder.mime_type(),
+        ))
+    }
+}
+
+/// The smallest page of a TIFF pyramid which is shrunk no more than `shrink`, if it has pages
+/// of the same aspect ratio at smaller sizes
+fn pyramid_page(filename: &str, full: &VipsImage, shrink: f64) -> Result<Option<VipsImage>> {
+    let mut best: Option<(
+        f64,
+        VipsImage,
+    )> = None;
+    for page in 1..full.get_n_pages() {
+        let level = VipsImage::tiffload_with_opts(
+            filename,
+            VOption::new().set(
+                "page",
+                v_value!(page),
+            ),
+        )?;
+        let factor_x = full.get_width() as f64 / level.get_width() as f64;
+        let factor_y = full.get_height() as f64 / level.get_height() as f64;
+        if (factor_x - factor_y).abs() > factor_x * 0.05 || factor_x > shrink {
+            // not a reduced copy of the first page, or too small
+            continue;
+        }
+        if best
+            .as_ref()
+            .is_none_or(|(best_factor, _)| factor_x > *best_factor)
+        {
+            best = Some((
+                factor_x,
+                level,
+            ));
+        }
+    }
+    Ok(best.map(|(_, level)| level))
+}
@@ -10,6 +10,7 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use std::borrow::Cow;
 use std::convert::TryInto;
 use std::ffi::*;
+use std::mem::MaybeUninit;
 use std::ptr::null_mut;
 
 const NULL: *const c_void = null_mut();
@@ -787,12 +788,14 @@ impl VipsImage {
         }
     }
 
+    /// Sets a string field. The value is null-terminated before it is passed to libvips, which
+    /// copies it.
     pub fn set_string(&self, name: impl AsRef<[u8]>, value: &str) {
         unsafe {
             bindings::vips_image_set_string(
                 self.ctx,
                 ensure_null_terminated(name).as_ptr() as _,
-                value.as_ptr() as _,
+                ensure_null_terminated(value).as_ptr() as _,
             )
         };
     }
@@ -819,12 +822,12 @@ impl VipsImage {
         }
     }
 
+    /// Sets a blob field to a copy of `blob`, so the slice need not outlive the image
     pub fn set_blob(&self, name: impl AsRef<[u8]>, blob: &[u8]) {
         unsafe {
-            bindings::vips_image_set_blob(
+            bindings::vips_image_set_blob_copy(
                 self.ctx,
                 ensure_null_terminated(name).as_ptr() as _,
-                None,
                 blob.as_ptr() as _,
                 blob.len() as _,
             )
@@ -905,6 +908,267 @@ impl VipsImage {
         }
     }
 
+    /// Returns the names of all header fields, including the built-in ones such as `width`
+    pub fn fields(&self) -> Vec<String> {
+        unsafe {
+            let fields = bindings::vips_image_get_fields(self.ctx);
+            let mut names = Vec::new();
+            let mut i = 0;
+            while !(*fields.add(i)).is_null() {
+                names.push(utils::c_string_lossy(*fields.add(i)));
+                i += 1;
+            }
+            bindings::g_strfreev(fields);
+            names
+        }
+    }
+
+    /// Reads a header field of any type
+    pub fn get(&self, name: impl AsRef<[u8]>) -> Result<MetaValue> {
+        unsafe {
+            let mut gvalue = MaybeUninit::<bindings::GValue>::zeroed();
+            let value = gvalue.as_mut_ptr();
+            let res = bindings::vips_image_get(
+                self.ctx,
+                ensure_null_terminated(name).as_ptr() as _,
+                value,
+            );
+            if res != 0 {
+                return Err(Error::IOError("Cannot get field"));
+            }
+
+            let value_type = (*value).g_type;
+            let meta = if value_type == utils::get_g_type(utils::G_TYPE_INT) {
+                MetaValue::Int(bindings::g_value_get_int(value))
+            } else if value_type == utils::get_g_type(utils::G_TYPE_DOUBLE) {
+                MetaValue::Double(bindings::g_value_get_double(value))
+            } else if value_type == utils::get_g_type(utils::G_TYPE_STRING) {
+                MetaValue::String(utils::c_string_lossy(bindings::g_value_get_string(value)))
+            } else if value_type == bindings::vips_ref_string_get_type() {
+                MetaValue::RefString(
+                    utils::c_string_lossy(
+                        bindings::vips_value_get_ref_string(
+                            value,
+                            null_mut(),
+                        ),
+                    ),
+                )
+            } else if value_type == bindings::vips_blob_get_type() {
+                let mut length = 0;
+                let data = bindings::vips_value_get_blob(
+                    value,
+                    &mut length,
+                );
+                MetaValue::Blob(
+                    std::slice::from_raw_parts(
+                        data as *const u8,
+                        length as usize,
+                    )
+                    .to_vec(),
+                )
+            } else if value_type == bindings::vips_image_get_type() {
+                MetaValue::Image(
+                    VipsImage::from(
+                        bindings::g_value_dup_object(value) as *mut bindings::VipsImage,
+                    ),
+                )
+            } else if value_type == bindings::vips_array_int_get_type() {
+                let mut size = 0;
+                let array = bindings::vips_value_get_array_int(
+                    value,
+                    &mut size,
+                );
+                MetaValue::ArrayInt(
+                    utils::new_int_array(
+                        array,
+                        size as _,
+                    ),
+                )
+            } else if value_type == bindings::vips_array_double_get_type() {
+                let mut size = 0;
+                let array = bindings::vips_value_get_array_double(
+                    value,
+                    &mut size,
+                );
+                MetaValue::ArrayDouble(
+                    utils::new_double_array(
+                        array,
+                        size as _,
+                    ),
+                )
+            } else if value_type == bindings::vips_array_image_get_type() {
+                let mut size = 0;
+                let array = bindings::vips_value_get_array_image(
+                    value,
+                    &mut size,
+                );
+                MetaValue::ArrayImage(
+                    std::slice::from_raw_parts(
+                        array,
+                        size as usize,
+                    )
+                    .iter()
+                    .map(|image| {
+                        bindings::g_object_ref(*image as _);
+                        VipsImage::from(*image)
+                    })
+                    .collect(),
+                )
+            } else if bindings::g_type_is_a(
+                value_type,
+                utils::get_g_type("GEnum"),
+            ) != 0
+            {
+                let enum_value = bindings::g_value_get_enum(value);
+                MetaValue::Enum {
+                    name: utils::c_string_lossy(bindings::g_type_name(value_type)),
+                    value: enum_value,
+                    nick: utils::c_string_lossy(
+                        bindings::vips_enum_nick(
+                            value_type,
+                            enum_value,
+                        ),
+                    ),
+                }
+            } else {
+                let contents = bindings::g_strdup_value_contents(value);
+                let text = utils::c_string_lossy(contents);
+                bindings::g_free(contents as _);
+                MetaValue::Other(text)
+            };
+            bindings::g_value_unset(value);
+            Ok(meta)
+        }
+    }
+
+    /// Sets a header field of any type, e.g. one read from another image with `get`
+    pub fn set(&self, name: impl AsRef<[u8]>, value: &MetaValue) -> Result<()> {
+        let name = ensure_null_terminated(name);
+        unsafe {
+            let name = name.as_ptr() as *const c_char;
+            match value {
+                MetaValue::Int(v) => bindings::vips_image_set_int(
+                    self.ctx,
+                    name,
+                    *v,
+                ),
+                MetaValue::Double(v) => bindings::vips_image_set_double(
+                    self.ctx,
+                    name,
+                    *v,
+                ),
+                MetaValue::String(v) => {
+                    let v = utils::new_c_string(v)?;
+                    bindings::vips_image_set_string(
+                        self.ctx,
+                        name,
+                        v.as_ptr(),
+                    )
+                }
+                MetaValue::RefString(v) => {
+                    let v = utils::new_c_string(v)?;
+                    let mut gvalue = MaybeUninit::<bindings::GValue>::zeroed();
+                    let gvalue_ptr = gvalue.as_mut_ptr();
+                    bindings::g_value_init(
+                        gvalue_ptr,
+                        bindings::vips_ref_string_get_type(),
+                    );
+                    bindings::vips_value_set_ref_string(
+                        gvalue_ptr,
+                        v.as_ptr(),
+                    );
+                    bindings::vips_image_set(
+                        self.ctx,
+                        name,
+                        gvalue_ptr,
+                    );
+                    bindings::g_value_unset(gvalue_ptr);
+                }
+                MetaValue::Blob(v) => bindings::vips_image_set_blob_copy(
+                    self.ctx,
+                    name,
+                    v.as_ptr() as _,
+                    v.len() as _,
+                ),
+                MetaValue::Image(v) => bindings::vips_image_set_image(
+                    self.ctx,
+                    name,
+                    v.ctx,
+                ),
+                MetaValue::ArrayInt(v) => bindings::vips_image_set_array_int(
+                    self.ctx,
+                    name,
+                    v.as_ptr(),
+                    v.len() as _,
+                ),
+                MetaValue::ArrayDouble(v) => bindings::vips_image_set_array_double(
+                    self.ctx,
+                    name,
+                    v.as_ptr(),
+                    v.len() as _,
+                ),
+                MetaValue::ArrayImage(v) => {
+                    let mut gvalue = MaybeUninit::<bindings::GValue>::zeroed();
+                    let gvalue_ptr = gvalue.as_mut_ptr();
+                    bindings::g_value_init(
+                        gvalue_ptr,
+                        bindings::vips_array_image_get_type(),
+                    );
+                    bindings::vips_value_set_array_image(
+                        gvalue_ptr,
+                        v.len() as _,
+                    );
+                    let array = std::slice::from_raw_parts_mut(
+                        bindings::vips_value_get_array_image(
+                            gvalue_ptr,
+                            null_mut(),
+                        ),
+                        v.len(),
+                    );
+                    for (slot, image) in array
+                        .iter_mut()
+                        .zip(v)
+                    {
+                        bindings::g_object_ref(image.ctx as _);
+                        *slot = image.ctx;
+                    }
+                    bindings::vips_image_set(
+                        self.ctx,
+                        name,
+                        gvalue_ptr,
+                    );
+                    bindings::g_value_unset(gvalue_ptr);
+                }
+                MetaValue::Enum {
+                    name: type_name,
+                    value: v,
+                    ..
+                } => {
+                    let mut gvalue = MaybeUninit::<bindings::GValue>::zeroed();
+                    let gvalue_ptr = gvalue.as_mut_ptr();
+                    bindings::g_value_init(
+                        gvalue_ptr,
+                        utils::get_g_type(type_name),
+                    );
+                    bindings::g_value_set_enum(
+                        gvalue_ptr,
+                        *v,
+                    );
+                    bindings::vips_image_set(
+                        self.ctx,
+                        name,
+                        gvalue_ptr,
+                    );
+                    bindings::g_value_unset(gvalue_ptr);
+                }
+                MetaValue::Other(_) => {
+                    return Err(Error::IOError("Cannot set field of unknown type"));
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn minpos(&self) -> Result<(f64, f64)> {
         let mut x: f64 = 0.0;
         let mut y: f64 = 0.0;
@@ -1266,6 +1530,64 @@ impl VipsTarget {
     }
 }
 
+/// The value of an image header field, as returned by `VipsImage::get`
+#[derive(Debug)]
+pub enum MetaValue {
+    Int(i32),
+    Double(f64),
+    String(String),
+    RefString(String),
+    Blob(Vec<u8>),
+    Image(VipsImage),
+    ArrayInt(Vec<i32>),
+    ArrayDouble(Vec<f64>),
+    ArrayImage(Vec<VipsImage>),
+    Enum {
+        name: String,
+        value: i32,
+        nick: String,
+    },
+    /// A field of any other type, as printed by GLib
+    Other(String),
+}
+
+impl PartialEq for MetaValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (
+            self, other,
+        ) {
+            (MetaValue::Int(a), MetaValue::Int(b)) => a == b,
+            (MetaValue::Double(a), MetaValue::Double(b)) => a == b,
+            (MetaValue::String(a), MetaValue::String(b))
+            | (MetaValue::RefString(a), MetaValue::RefString(b))
+            | (MetaValue::Other(a), MetaValue::Other(b)) => a == b,
+            (MetaValue::Blob(a), MetaValue::Blob(b)) => a == b,
+            (MetaValue::Image(a), MetaValue::Image(b)) => a.ctx == b.ctx,
+            (MetaValue::ArrayInt(a), MetaValue::ArrayInt(b)) => a == b,
+            (MetaValue::ArrayDouble(a), MetaValue::ArrayDouble(b)) => a == b,
+            (MetaValue::ArrayImage(a), MetaValue::ArrayImage(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|(a, b)| a.ctx == b.ctx)
+            }
+            (
+                MetaValue::Enum {
+                    name: a_name,
+                    value: a_value,
+                    ..
+                },
+                MetaValue::Enum {
+                    name: b_name,
+                    value: b_value,
+                    ..
+                },
+            ) => a_name == b_name && a_value == b_value,
+            _ => false,
+        }
+    }
+}
+
 unsafe fn vips_image_result(res: *mut bindings::VipsImage, err: Error) -> Result<VipsImage> {
     if res.is_null() {
         Err(err)
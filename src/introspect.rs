@@ -0,0 +1,296 @@
+//! Operation introspection
+//!
+//! Looks up vips operations and their arguments at runtime, the same way the `vips` command does.
+use crate::{
+    bindings::{self, GEnumClass, GFlagsClass, GParamSpec, GType, VipsObject, VipsOperationClass},
+    error::Error,
+    utils::{self, G_TYPE_BOOLEAN, G_TYPE_DOUBLE, G_TYPE_INT, G_TYPE_STRING, G_TYPE_UINT64},
+    Result,
+};
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+
+/// The type of value an operation argument accepts or produces
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgumentType {
+    Bool,
+    Int,
+    Uint64,
+    Double,
+    String,
+    /// An enumeration, with the nicks it accepts
+    Enum {
+        name: String,
+        nicks: Vec<String>,
+    },
+    /// A set of flags, with the nicks it accepts
+    Flags {
+        name: String,
+        nicks: Vec<String>,
+    },
+    Image,
+    ImageArray,
+    IntArray,
+    DoubleArray,
+    Blob,
+    Source,
+    Target,
+    Interpolate,
+    /// Any other GType, by name
+    Other(String),
+}
+
+/// An argument of a vips operation
+#[derive(Debug, Clone)]
+pub struct Argument {
+    pub name: String,
+    pub blurb: String,
+    pub kind: ArgumentType,
+    pub required: bool,
+    pub input: bool,
+    pub output: bool,
+    pub modify: bool,
+    pub deprecated: bool,
+}
+
+/// A vips operation and its arguments, in the order vips declares them
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub nickname: String,
+    pub description: String,
+    pub deprecated: bool,
+    pub arguments: Vec<Argument>,
+}
+
+impl Operation {
+    /// Looks up an operation by nickname, e.g. `thumbnail_image`
+    pub fn find(nickname: &str) -> Result<Operation> {
+        unsafe {
+            let name = utils::new_c_string(nickname)?;
+            let operation = bindings::vips_operation_new(name.as_ptr());
+            if operation.is_null() {
+                bindings::vips_error_clear();
+                return Err(
+                    Error::OperationErrorExt(format!(
+                        "Unknown operation `{}`",
+                        nickname
+                    )),
+                );
+            }
+
+            let object: *mut VipsObject = operation.cast();
+            let deprecated = bindings::vips_operation_get_flags(operation)
+                & bindings::VipsOperationFlags_VIPS_OPERATION_DEPRECATED
+                != 0;
+
+            let mut names: *mut *const c_char = null_mut();
+            let mut flags: *mut i32 = null_mut();
+            let mut n_args = 0;
+            bindings::vips_object_get_args(
+                object,
+                &mut names,
+                &mut flags,
+                &mut n_args,
+            );
+
+            let mut arguments = Vec::with_capacity(n_args as usize);
+            for i in 0..n_args as usize {
+                let arg_name = *names.add(i);
+                let arg_flags = *flags.add(i) as u32;
+                let mut pspec: *mut GParamSpec = null_mut();
+                let mut argument_class = null_mut();
+                let mut argument_instance = null_mut();
+                if bindings::vips_object_get_argument(
+                    object,
+                    arg_name,
+                    &mut pspec,
+                    &mut argument_class,
+                    &mut argument_instance,
+                ) != 0
+                {
+                    bindings::vips_error_clear();
+                    continue;
+                }
+
+                arguments.push(Argument {
+                    name: utils::c_string_lossy(arg_name),
+                    blurb: utils::c_string_lossy(bindings::g_param_spec_get_blurb(pspec)),
+                    kind: argument_type((*pspec).value_type),
+                    required: arg_flags & bindings::VipsArgumentFlags_VIPS_ARGUMENT_REQUIRED != 0,
+                    input: arg_flags & bindings::VipsArgumentFlags_VIPS_ARGUMENT_INPUT != 0,
+                    output: arg_flags & bindings::VipsArgumentFlags_VIPS_ARGUMENT_OUTPUT != 0,
+                    modify: arg_flags & bindings::VipsArgumentFlags_VIPS_ARGUMENT_MODIFY != 0,
+                    deprecated: arg_flags & bindings::VipsArgumentFlags_VIPS_ARGUMENT_DEPRECATED
+                        != 0,
+                });
+            }
+
+            let result = Operation {
+                nickname: utils::c_string_lossy((*object).nickname),
+                description: utils::c_string_lossy((*object).description),
+                deprecated,
+                arguments,
+            };
+            bindings::g_object_unref(operation as _);
+            Ok(result)
+        }
+    }
+
+    /// Returns the argument with the given name
+    pub fn argument(&self, name: &str) -> Option<&Argument> {
+        self.arguments
+            .iter()
+            .find(|arg| arg.name == name)
+    }
+
+    /// Required input arguments, in positional order
+    pub fn required_inputs(&self) -> impl Iterator<Item = &Argument> {
+        self.arguments
+            .iter()
+            .filter(|arg| arg.required && arg.input && !arg.deprecated)
+    }
+
+    /// Optional input arguments
+    pub fn optional_inputs(&self) -> impl Iterator<Item = &Argument> {
+        self.arguments
+            .iter()
+            .filter(|arg| !arg.required && arg.input && !arg.deprecated)
+    }
+
+    /// Output arguments, required ones first
+    pub fn outputs(&self) -> impl Iterator<Item = &Argument> {
+        self.arguments
+            .iter()
+            .filter(|arg| arg.required && arg.output && !arg.deprecated)
+            .chain(
+                self.arguments
+                    .iter()
+                    .filter(|arg| !arg.required && arg.output && !arg.deprecated),
+            )
+    }
+}
+
+/// Lists the nicknames of all operations which can be built, skipping deprecated ones
+pub fn operations() -> Vec<String> {
+    let mut nicknames: Vec<String> = Vec::new();
+    unsafe {
+        bindings::vips_type_map_all(
+            bindings::vips_operation_get_type(),
+            Some(collect_operation),
+            &mut nicknames as *mut Vec<String> as *mut c_void,
+        );
+    }
+    nicknames.sort();
+    nicknames.dedup();
+    nicknames
+}
+
+unsafe extern "C" fn collect_operation(type_: GType, a: *mut c_void) -> *mut c_void {
+    if bindings::g_type_test_flags(
+        type_,
+        bindings::GTypeFlags_G_TYPE_FLAG_ABSTRACT,
+    ) != 0
+    {
+        return null_mut();
+    }
+
+    let class = bindings::g_type_class_ref(type_) as *mut VipsOperationClass;
+    let deprecated = (*class).flags & bindings::VipsOperationFlags_VIPS_OPERATION_DEPRECATED != 0
+        || (*class)
+            .parent_class
+            .deprecated
+            != 0;
+    bindings::g_type_class_unref(class as _);
+
+    let nickname = bindings::vips_nickname_find(type_);
+    if !deprecated && !nickname.is_null() {
+        let nicknames = &mut *(a as *mut Vec<String>);
+        nicknames.push(utils::c_string_lossy(nickname));
+    }
+    null_mut()
+}
+
+fn argument_type(value_type: GType) -> ArgumentType {
+    unsafe {
+        let is_a = |name: &str| {
+            bindings::g_type_is_a(
+                value_type,
+                utils::get_g_type(name),
+            ) != 0
+        };
+        if value_type == utils::get_g_type(G_TYPE_BOOLEAN) {
+            ArgumentType::Bool
+        } else if value_type == utils::get_g_type(G_TYPE_INT) {
+            ArgumentType::Int
+        } else if value_type == utils::get_g_type(G_TYPE_UINT64) {
+            ArgumentType::Uint64
+        } else if value_type == utils::get_g_type(G_TYPE_DOUBLE) {
+            ArgumentType::Double
+        } else if value_type == utils::get_g_type(G_TYPE_STRING) {
+            ArgumentType::String
+        } else if is_a("GEnum") {
+            let class = bindings::g_type_class_ref(value_type) as *mut GEnumClass;
+            let values = std::slice::from_raw_parts(
+                (*class).values,
+                (*class).n_values as usize,
+            );
+            let nicks = values
+                .iter()
+                .map(|value| utils::c_string_lossy(value.value_nick))
+                .filter(|nick| nick != "last")
+                .collect();
+            bindings::g_type_class_unref(class as _);
+            ArgumentType::Enum {
+                name: utils::c_string_lossy(bindings::g_type_name(value_type)),
+                nicks,
+            }
+        } else if is_a("GFlags") {
+            let class = bindings::g_type_class_ref(value_type) as *mut GFlagsClass;
+            let values = std::slice::from_raw_parts(
+                (*class).values,
+                (*class).n_values as usize,
+            );
+            let nicks = values
+                .iter()
+                .map(|value| utils::c_string_lossy(value.value_nick))
+                .filter(|nick| nick != "last")
+                .collect();
+            bindings::g_type_class_unref(class as _);
+            ArgumentType::Flags {
+                name: utils::c_string_lossy(bindings::g_type_name(value_type)),
+                nicks,
+            }
+        } else if value_type == bindings::vips_image_get_type() {
+            ArgumentType::Image
+        } else if value_type == bindings::vips_array_image_get_type() {
+            ArgumentType::ImageArray
+        } else if value_type == bindings::vips_array_int_get_type() {
+            ArgumentType::IntArray
+        } else if value_type == bindings::vips_array_double_get_type() {
+            ArgumentType::DoubleArray
+        } else if value_type == bindings::vips_blob_get_type() {
+            ArgumentType::Blob
+        } else if bindings::g_type_is_a(
+            value_type,
+            bindings::vips_source_get_type(),
+        ) != 0
+        {
+            ArgumentType::Source
+        } else if bindings::g_type_is_a(
+            value_type,
+            bindings::vips_target_get_type(),
+        ) != 0
+        {
+            ArgumentType::Target
+        } else if bindings::g_type_is_a(
+            value_type,
+            bindings::vips_interpolate_get_type(),
+        ) != 0
+        {
+            ArgumentType::Interpolate
+        } else {
+            ArgumentType::Other(utils::c_string_lossy(bindings::g_type_name(value_type)))
+        }
+    }
+}
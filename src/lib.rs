@@ -7,12 +7,49 @@
 extern crate num_derive;
 extern crate num_traits;
 
+/// Multi-page and animated images
+pub mod animation;
 pub mod bindings;
+/// A mutable image for the draw operations
+pub mod canvas;
+/// Image quality metrics
+pub mod compare;
+/// Encoding and content negotiation
+pub mod encode;
 pub mod error;
+/// Image format detection and loader and saver discovery
+pub mod format;
+/// Perceptual image hashes
+pub mod hash;
+/// ICC profile management
+pub mod icc;
+/// IIIF Image API requests
+pub mod iiif;
 mod image;
+/// Operation and argument introspection
+pub mod introspect;
+/// Convolution and morphology kernels
+pub mod matrix;
+/// EXIF, XMP and IPTC metadata
+pub mod metadata;
 pub mod operator;
 /// Vips Enumerations
 pub mod ops;
+/// Watermarks and overlays
+pub mod overlay;
+/// Recorded pipelines of operations
+pub mod pipeline;
+/// Tile pyramids streamed to a callback
+pub mod pyramid;
+/// Resizing with sharp style fit modes
+pub mod resize;
+/// Image statistics and histograms
+pub mod stats;
+/// Golden image assertions for tests
+#[cfg(feature = "testing")]
+pub mod testing;
+/// Text rendering and captions
+pub mod text;
 pub mod utils;
 /// VOption, a list of name-value pairs
 pub mod voption;
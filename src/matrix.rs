@@ -0,0 +1,357 @@
+//! Convolution and morphology kernels
+//!
+//! libvips passes kernels around as one band double images whose `scale` and `offset` fields
+//! divide and shift the result of a convolution. [`Matrix`] holds the same data in Rust and
+//! reads and writes the `.mat` text format of `matrixload` and `matrixsave`:
+//!
+//! ```text
+//! 3 3 8 0
+//! -1 -1 -1
+//! -1 16 -1
+//! -1 -1 -1
+//! ```
+use crate::{
+    error::Error,
+    ops::{BandFormat, OperationMorphology},
+    Result, VipsImage,
+};
+use std::fmt;
+use std::str::FromStr;
+
+/// A kernel for `conv`, `convsep`, `morph` and `compass`. The size and values are read with
+/// getters, so every matrix has `width * height` values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    width: i32,
+    height: i32,
+    /// Convolution results are divided by this
+    pub scale: f64,
+    /// and then this is added
+    pub offset: f64,
+    data: Vec<f64>,
+}
+
+impl Matrix {
+    /// A matrix with scale 1 and offset 0
+    pub fn new(width: i32, height: i32, data: Vec<f64>) -> Result<Matrix> {
+        let size = width
+            .checked_mul(height)
+            .ok_or(Error::OperationError("Matrix is too large"))?;
+        if width <= 0 || height <= 0 || data.len() != size as usize {
+            return Err(Error::OperationError("Matrix data does not match its size"));
+        }
+        Ok(Matrix {
+            width,
+            height,
+            scale: 1.0,
+            offset: 0.0,
+            data,
+        })
+    }
+
+    pub fn with_scale(mut self, scale: f64, offset: f64) -> Matrix {
+        self.scale = scale;
+        self.offset = offset;
+        self
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Row-major values, `width * height` of them
+    pub fn data(&self) -> &[f64] {
+        &self.data
+    }
+
+    /// The value at `x`, `y`. Panics if that is outside the matrix.
+    pub fn get(&self, x: i32, y: i32) -> f64 {
+        assert!(
+            (0..self.width).contains(&x) && (0..self.height).contains(&y),
+            "Matrix position out of range"
+        );
+        self.data[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Reads a matrix image, e.g. from `gaussmat` or `matrixload`
+    pub fn from_image(image: &VipsImage) -> Result<Matrix> {
+        if image.get_bands() != 1 {
+            return Err(Error::OperationError("A matrix image has one band"));
+        }
+        let data = image
+            .cast(BandFormat::Double)?
+            .image_write_to_memory()
+            .chunks_exact(8)
+            .map(|b| f64::from_ne_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect();
+        Ok(
+            Matrix::new(
+                image.get_width(),
+                image.get_height(),
+                data,
+            )?
+            .with_scale(
+                image.get_scale(),
+                image.get_offset(),
+            ),
+        )
+    }
+
+    /// A matrix image to pass as `mask`
+    pub fn to_image(&self) -> Result<VipsImage> {
+        let image = VipsImage::new_matrix_from_array(
+            self.width,
+            self.height,
+            &self.data,
+        )?;
+        image.set_double(
+            "scale",
+            self.scale,
+        );
+        image.set_double(
+            "offset",
+            self.offset,
+        );
+        Ok(image)
+    }
+
+    /// Loads a `.mat` file
+    pub fn from_file(filename: &str) -> Result<Matrix> {
+        std::fs::read_to_string(filename)
+            .map_err(|_| Error::IOError("Cannot read matrix file"))?
+            .parse()
+    }
+
+    /// An averaging kernel of `size` x `size` ones, scaled to sum to 1
+    pub fn box_blur(size: i32) -> Result<Matrix> {
+        let n = size
+            .checked_mul(size)
+            .ok_or(Error::OperationError("Matrix is too large"))?;
+        Ok(
+            Matrix::new(
+                size,
+                size,
+                vec![1.0; n.max(0) as usize],
+            )?
+            .with_scale(
+                n as f64,
+                0.0,
+            ),
+        )
+    }
+
+    /// A Gaussian kernel made by `gaussmat`, large enough to reach `min_ampl`, e.g. 0.2
+    pub fn gaussian(sigma: f64, min_ampl: f64) -> Result<Matrix> {
+        Matrix::from_image(
+            &VipsImage::gaussmat(
+                sigma,
+                min_ampl,
+            )?,
+        )
+    }
+
+    /// The 3x3 Laplacian, which responds to edges in every direction
+    pub fn laplacian() -> Matrix {
+        Matrix {
+            width: 3,
+            height: 3,
+            scale: 1.0,
+            offset: 0.0,
+            data: vec![0.0, -1.0, 0.0, -1.0, 4.0, -1.0, 0.0, -1.0, 0.0],
+        }
+    }
+
+    /// The 3x3 Sobel kernel for horizontal gradients, i.e. vertical edges
+    pub fn sobel_x() -> Matrix {
+        Matrix {
+            width: 3,
+            height: 3,
+            scale: 1.0,
+            offset: 0.0,
+            data: vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0],
+        }
+    }
+
+    /// The 3x3 Sobel kernel for vertical gradients, i.e. horizontal edges
+    pub fn sobel_y() -> Matrix {
+        Matrix {
+            width: 3,
+            height: 3,
+            scale: 1.0,
+            offset: 0.0,
+            data: vec![-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0],
+        }
+    }
+}
+
+impl FromStr for Matrix {
+    type Err = Error;
+
+    /// Parses the `.mat` format: `width height [scale [offset]]`, then the values row by row
+    fn from_str(text: &str) -> Result<Matrix> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        let header: Vec<f64> = lines
+            .next()
+            .ok_or(Error::OperationError("Empty matrix"))?
+            .split_whitespace()
+            .map(|item| item.parse::<f64>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| Error::OperationError("Bad matrix header"))?;
+        let side = |value: f64| value.fract() == 0.0 && (1.0..=i32::MAX as f64).contains(&value);
+        if !(2..=4).contains(&header.len()) || !side(header[0]) || !side(header[1]) {
+            return Err(Error::OperationError("Bad matrix header"));
+        }
+        let data = lines
+            .flat_map(str::split_whitespace)
+            .map(|item| item.parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| Error::OperationError("Bad matrix value"))?;
+        Ok(
+            Matrix::new(
+                header[0] as i32,
+                header[1] as i32,
+                data,
+            )?
+            .with_scale(
+                header
+                    .get(2)
+                    .copied()
+                    .unwrap_or(1.0),
+                header
+                    .get(3)
+                    .copied()
+                    .unwrap_or(0.0),
+            ),
+        )
+    }
+}
+
+impl fmt::Display for Matrix {
+    /// Writes the `.mat` format
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} {} {} {}",
+            self.width, self.height, self.scale, self.offset
+        )?;
+        for row in self
+            .data
+            .chunks(self.width as usize)
+        {
+            let row: Vec<String> = row
+                .iter()
+                .map(f64::to_string)
+                .collect();
+            writeln!(
+                f,
+                "{}",
+                row.join(" ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl VipsImage {
+    /// `conv` with a [`Matrix`] mask
+    pub fn conv_matrix(&self, mask: &Matrix) -> Result<VipsImage> {
+        self.conv(&mask.to_image()?)
+    }
+
+    /// `convsep` with a [`Matrix`] mask, which must be a single row or column
+    pub fn convsep_matrix(&self, mask: &Matrix) -> Result<VipsImage> {
+        self.convsep(&mask.to_image()?)
+    }
+
+    /// `morph` with a [`Matrix`] mask of 0, 255 and 128 for don't care
+    pub fn morph_matrix(&self, mask: &Matrix, morph: OperationMorphology) -> Result<VipsImage> {
+        self.morph(
+            &mask.to_image()?,
+            morph,
+        )
+    }
+
+    /// `compass` with a [`Matrix`] mask
+    pub fn compass_matrix(&self, mask: &Matrix) -> Result<VipsImage> {
+        self.compass(&mask.to_image()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_rejects_sizes_which_overflow() {
+        assert!(
+            Matrix::new(
+                100000,
+                100000,
+                vec![]
+            )
+            .is_err()
+        );
+        assert!(
+            Matrix::new(
+                i32::MAX,
+                2,
+                vec![]
+            )
+            .is_err()
+        );
+        assert!(
+            Matrix::new(
+                -1,
+                -1,
+                vec![1.0]
+            )
+            .is_err()
+        );
+        assert!(Matrix::box_blur(100000).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_bad_headers() {
+        assert!(
+            "100000 100000\n1\n"
+                .parse::<Matrix>()
+                .is_err()
+        );
+        assert!(
+            "3000000000 1\n1\n"
+                .parse::<Matrix>()
+                .is_err()
+        );
+        assert!("0 1\n"
+            .parse::<Matrix>()
+            .is_err());
+        assert!(
+            "1.5 1\n1\n"
+                .parse::<Matrix>()
+                .is_err()
+        );
+        assert!(
+            "NaN 1\n1\n"
+                .parse::<Matrix>()
+                .is_err()
+        );
+        let matrix: Matrix = "2 1 2\n1 3\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            matrix.get(1, 0),
+            3.0
+        );
+        assert_eq!(
+            matrix.scale,
+            2.0
+        );
+    }
+}
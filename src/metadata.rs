@@ -0,0 +1,717 @@
+//! EXIF, XMP and IPTC metadata
+//!
+//! libvips keeps the raw EXIF block in `exif-data` and also parses every tag into a string field
+//! named `exif-ifdN-TagName`, formatted as `raw value (description, format, n components, n bytes)`.
+//! When an image is saved, libvips rebuilds `exif-data` from those string fields, so the setters
+//! here only need to update the fields.
+use crate::{
+    error::Error,
+    ops::{Angle, Direction},
+    Result, VipsImage,
+};
+
+pub const EXIF_NAME: &str = "exif-data";
+pub const XMP_NAME: &str = "xmp-data";
+pub const IPTC_NAME: &str = "iptc-data";
+pub const ORIENTATION_NAME: &str = "orientation";
+
+/// What `VipsImage::auto_orient` did to the pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AppliedOrientation {
+    /// The orientation the image had, 1 when it had none
+    pub orientation: i32,
+    /// Clockwise rotation, applied first
+    pub angle: Angle,
+    /// Then a horizontal flip
+    pub flipped: bool,
+}
+
+impl AppliedOrientation {
+    /// Whether the pixels were left as they were
+    pub fn is_identity(&self) -> bool {
+        self.angle == Angle::D0 && !self.flipped
+    }
+}
+
+/// A date and time as stored in EXIF, `YYYY:MM:DD HH:MM:SS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExifDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl ExifDateTime {
+    /// Parses the EXIF `YYYY:MM:DD HH:MM:SS` format
+    pub fn parse(text: &str) -> Option<ExifDateTime> {
+        let (date, time) = text
+            .trim()
+            .split_once(' ')?;
+        let date: Vec<&str> = date
+            .split(':')
+            .collect();
+        let time: Vec<&str> = time
+            .split(':')
+            .collect();
+        if date.len() != 3 || time.len() != 3 {
+            return None;
+        }
+        let date_time = ExifDateTime {
+            year: date[0]
+                .parse()
+                .ok()?,
+            month: date[1]
+                .parse()
+                .ok()?,
+            day: date[2]
+                .parse()
+                .ok()?,
+            hour: time[0]
+                .parse()
+                .ok()?,
+            minute: time[1]
+                .parse()
+                .ok()?,
+            second: time[2]
+                .parse()
+                .ok()?,
+        };
+        // cameras without a clock write zeros or blanks
+        let valid = (1..=12).contains(&date_time.month)
+            && (1..=31).contains(&date_time.day)
+            && date_time.hour < 24
+            && date_time.minute < 60
+            && date_time.second <= 60;
+        valid.then_some(date_time)
+    }
+}
+
+impl std::fmt::Display for ExifDateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// GPS position in decimal degrees, negative for south and west
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsCoordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level, negative below it
+    pub altitude: Option<f64>,
+}
+
+/// A view of the EXIF fields of an image, see `VipsImage::exif`
+pub struct Exif<'a> {
+    image: &'a VipsImage,
+}
+
+impl<'a> Exif<'a> {
+    /// Returns the raw value of a tag, e.g. `("ifd0", "Make")`, without the libvips description
+    pub fn get(&self, ifd: &str, tag: &str) -> Option<String> {
+        let field = self
+            .image
+            .get_string(field_name(ifd, tag))
+            .ok()?;
+        Some(raw_value(&field).to_string())
+    }
+
+    /// Sets an ASCII tag. libvips writes it into `exif-data` when the image is saved.
+    pub fn set(&self, ifd: &str, tag: &str, value: &str) {
+        let length = value.len() + 1;
+        self.image
+            .set_string(
+                field_name(ifd, tag),
+                &format!(
+                    "{} ({}, ASCII, {} components, {} bytes)",
+                    value, value, length, length
+                ),
+            );
+    }
+
+    /// Removes a tag, so it is dropped from `exif-data` when the image is saved
+    pub fn remove(&self, ifd: &str, tag: &str) -> bool {
+        self.image
+            .remove(field_name(ifd, tag))
+    }
+
+    /// The names of all parsed EXIF fields, e.g. `exif-ifd0-Make`
+    pub fn fields(&self) -> Vec<String> {
+        self.image
+            .fields()
+            .into_iter()
+            .filter(|name| name.starts_with("exif-ifd"))
+            .collect()
+    }
+
+    /// The raw EXIF block
+    pub fn data(&self) -> Option<Vec<u8>> {
+        self.image
+            .get_blob(EXIF_NAME)
+            .ok()
+    }
+
+    /// Removes the EXIF block and all parsed EXIF fields
+    pub fn clear(&self) {
+        for name in self.fields() {
+            self.image
+                .remove(&name);
+        }
+        self.image
+            .remove(EXIF_NAME);
+    }
+
+    pub fn make(&self) -> Option<String> {
+        self.get(
+            "ifd0",
+            "Make",
+        )
+    }
+
+    pub fn set_make(&self, make: &str) {
+        self.set(
+            "ifd0",
+            "Make",
+            make,
+        )
+    }
+
+    pub fn model(&self) -> Option<String> {
+        self.get(
+            "ifd0",
+            "Model",
+        )
+    }
+
+    pub fn set_model(&self, model: &str) {
+        self.set(
+            "ifd0",
+            "Model",
+            model,
+        )
+    }
+
+    pub fn software(&self) -> Option<String> {
+        self.get(
+            "ifd0",
+            "Software",
+        )
+    }
+
+    pub fn lens_model(&self) -> Option<String> {
+        self.get(
+            "ifd2",
+            "LensModel",
+        )
+    }
+
+    pub fn date_time_original(&self) -> Option<ExifDateTime> {
+        ExifDateTime::parse(&self.get(
+            "ifd2",
+            "DateTimeOriginal",
+        )?)
+    }
+
+    pub fn set_date_time_original(&self, date_time: &ExifDateTime) {
+        self.set(
+            "ifd2",
+            "DateTimeOriginal",
+            &date_time.to_string(),
+        )
+    }
+
+    /// Exposure time in seconds
+    pub fn exposure_time(&self) -> Option<f64> {
+        parse_rationals(&self.get(
+            "ifd2",
+            "ExposureTime",
+        )?)
+        .first()
+        .copied()
+    }
+
+    pub fn f_number(&self) -> Option<f64> {
+        parse_rationals(&self.get(
+            "ifd2",
+            "FNumber",
+        )?)
+        .first()
+        .copied()
+    }
+
+    pub fn iso(&self) -> Option<i32> {
+        self.get(
+            "ifd2",
+            "ISOSpeedRatings",
+        )?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+    }
+
+    /// Focal length in millimetres
+    pub fn focal_length(&self) -> Option<f64> {
+        parse_rationals(&self.get(
+            "ifd2",
+            "FocalLength",
+        )?)
+        .first()
+        .copied()
+    }
+
+    /// The orientation, 1 to 8. The `orientation` field libvips keeps in sync is used when set.
+    pub fn orientation(&self) -> Option<i32> {
+        if let Ok(orientation) = self
+            .image
+            .get_int(ORIENTATION_NAME)
+        {
+            return Some(orientation);
+        }
+        self.get(
+            "ifd0",
+            "Orientation",
+        )?
+        .trim()
+        .parse()
+        .ok()
+    }
+
+    /// Sets the orientation. libvips writes it into `exif-data` from the `orientation` field on save.
+    pub fn set_orientation(&self, orientation: i32) -> Result<()> {
+        if !(1..=8).contains(&orientation) {
+            return Err(Error::OperationError("Orientation must be between 1 and 8"));
+        }
+        self.image
+            .set_int(
+                ORIENTATION_NAME,
+                orientation,
+            );
+        Ok(())
+    }
+
+    pub fn gps(&self) -> Option<GpsCoordinates> {
+        let latitude = degrees(&self.get(
+            "ifd3",
+            "GPSLatitude",
+        )?)?;
+        let longitude = degrees(&self.get(
+            "ifd3",
+            "GPSLongitude",
+        )?)?;
+        let south = self
+            .get(
+                "ifd3",
+                "GPSLatitudeRef",
+            )
+            .is_some_and(|r| r.starts_with('S'));
+        let west = self
+            .get(
+                "ifd3",
+                "GPSLongitudeRef",
+            )
+            .is_some_and(|r| r.starts_with('W'));
+        let altitude = self
+            .get(
+                "ifd3",
+                "GPSAltitude",
+            )
+            .and_then(|a| {
+                parse_rationals(&a)
+                    .first()
+                    .copied()
+            })
+            .map(
+                |altitude| {
+                    let below = self
+                        .get(
+                            "ifd3",
+                            "GPSAltitudeRef",
+                        )
+                        .is_some_and(|r| r.trim() == "1");
+                    if below {
+                        -altitude
+                    } else {
+                        altitude
+                    }
+                },
+            );
+
+        Some(
+            GpsCoordinates {
+                latitude: if south { -latitude } else { latitude },
+                longitude: if west { -longitude } else { longitude },
+                altitude,
+            },
+        )
+    }
+
+    /// Removes all GPS tags, e.g. before publishing an upload
+    pub fn remove_gps(&self) {
+        for name in self
+            .fields()
+            .into_iter()
+            .filter(|name| name.starts_with("exif-ifd3-"))
+        {
+            self.image
+                .remove(&name);
+        }
+    }
+}
+
+impl VipsImage {
+    /// A view of the EXIF fields of this image
+    pub fn exif(&self) -> Exif<'_> {
+        Exif {
+            image: self,
+        }
+    }
+
+    /// The XMP packet, if the image has one
+    pub fn xmp(&self) -> Option<String> {
+        let data = self
+            .get_blob(XMP_NAME)
+            .ok()?;
+        Some(
+            String::from_utf8_lossy(&data)
+                .trim_end_matches('\0')
+                .to_string(),
+        )
+    }
+
+    pub fn set_xmp(&self, xml: &str) {
+        self.set_blob(
+            XMP_NAME,
+            xml.as_bytes(),
+        );
+    }
+
+    /// The IPTC block, if the image has one
+    pub fn iptc(&self) -> Option<Vec<u8>> {
+        self.get_blob(IPTC_NAME)
+            .ok()
+    }
+
+    pub fn set_iptc(&self, iptc: &[u8]) {
+        self.set_blob(
+            IPTC_NAME,
+            iptc,
+        );
+    }
+}
+
+impl VipsImage {
+    /// Turns the pixels upright as the EXIF orientation says, then sets the orientation to 1 and
+    /// drops the parsed EXIF tag, so savers and viewers do not rotate the image again.
+    ///
+    /// Orientations 2, 4, 5 and 7 also mirror the image. They are left as they are, metadata
+    /// included, unless `flip` is set.
+    pub fn auto_orient(
+        &self,
+        flip: bool,
+    ) -> Result<(
+        VipsImage,
+        AppliedOrientation,
+    )> {
+        let orientation = self
+            .exif()
+            .orientation()
+            .filter(|orientation| (1..=8).contains(orientation))
+            .unwrap_or(1);
+        // the same rotate-then-flip steps as autorot
+        let (angle, mirrored) = match orientation {
+            2 => (
+                Angle::D0,
+                true,
+            ),
+            3 => (
+                Angle::D180,
+                false,
+            ),
+            4 => (
+                Angle::D180,
+                true,
+            ),
+            5 => (
+                Angle::D90,
+                true,
+            ),
+            6 => (
+                Angle::D90,
+                false,
+            ),
+            7 => (
+                Angle::D270,
+                true,
+            ),
+            8 => (
+                Angle::D270,
+                false,
+            ),
+            _ => (
+                Angle::D0,
+                false,
+            ),
+        };
+        if mirrored && !flip {
+            return Ok((
+                self.copy()?,
+                AppliedOrientation {
+                    orientation,
+                    angle: Angle::D0,
+                    flipped: false,
+                },
+            ));
+        }
+        let mut image = self.rot(angle)?;
+        if mirrored {
+            image = image.flip(Direction::Horizontal)?;
+        }
+        // rot and flip results are cached, so the metadata is changed on a copy
+        let image = image.copy()?;
+        let exif = image.exif();
+        exif.set_orientation(1)?;
+        exif.remove(
+            "ifd0",
+            "Orientation",
+        );
+        Ok((
+            image,
+            AppliedOrientation {
+                orientation,
+                angle,
+                flipped: mirrored,
+            },
+        ))
+    }
+}
+
+fn field_name(ifd: &str, tag: &str) -> String {
+    format!(
+        "exif-{}-{}",
+        ifd, tag
+    )
+}
+
+/// Strips the ` (description, format, n components, n bytes)` suffix libvips appends. The
+/// description can contain `" ("` and `", "` too, so the suffix is parsed from the right.
+fn raw_value(field: &str) -> &str {
+    let Some(inner) = field.strip_suffix(" bytes)") else {
+        return field;
+    };
+    let mut parts = inner.rsplitn(4, ", ");
+    let (Some(bytes), Some(components), Some(_format), Some(head)) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return field;
+    };
+    let counted = |n: &str| {
+        n.parse::<u64>()
+            .is_ok()
+    };
+    if !counted(bytes)
+        || !components
+            .strip_suffix(" components")
+            .is_some_and(counted)
+    {
+        return field;
+    }
+    // ASCII values are repeated as their description, so they split in the middle
+    let middle = head
+        .len()
+        .saturating_sub(2)
+        / 2;
+    if head.len() % 2 == 0
+        && head.get(middle..middle + 2) == Some(" (")
+        && head[..middle] == head[middle + 2..]
+    {
+        return &field[..middle];
+    }
+    // other values are numbers, which have no brackets
+    head.find(" (")
+        .map_or(
+            field,
+            |index| &field[..index],
+        )
+}
+
+/// Parses rationals such as `51/1 30/1 2604/100`
+fn parse_rationals(value: &str) -> Vec<f64> {
+    value
+        .split_whitespace()
+        .filter_map(
+            |item| match item.split_once('/') {
+                Some((numerator, denominator)) => {
+                    let numerator: f64 = numerator
+                        .parse()
+                        .ok()?;
+                    let denominator: f64 = denominator
+                        .parse()
+                        .ok()?;
+                    if denominator == 0.0 {
+                        None
+                    } else {
+                        Some(numerator / denominator)
+                    }
+                }
+                None => item
+                    .parse()
+                    .ok(),
+            },
+        )
+        .collect()
+}
+
+/// Degrees, minutes and seconds as decimal degrees. Any unreadable part makes the whole value
+/// unreadable, rather than shifting the parts after it.
+fn degrees(value: &str) -> Option<f64> {
+    let parts = parse_rationals(value);
+    if parts.len()
+        != value
+            .split_whitespace()
+            .count()
+    {
+        return None;
+    }
+    let degrees = parts.first()?;
+    let minutes = parts
+        .get(1)
+        .unwrap_or(&0.0);
+    let seconds = parts
+        .get(2)
+        .unwrap_or(&0.0);
+    Some(degrees + minutes / 60.0 + seconds / 3600.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_value_strips_only_a_trailing_description() {
+        assert_eq!(
+            raw_value("Canon (Canon, ASCII, 6 components, 6 bytes)"),
+            "Canon"
+        );
+        assert_eq!(
+            raw_value("no description"),
+            "no description"
+        );
+        assert_eq!(
+            raw_value("open (paren"),
+            "open (paren"
+        );
+        assert_eq!(
+            raw_value(" ()"),
+            " ()"
+        );
+        assert_eq!(
+            raw_value(
+                "Adobe Photoshop CC 2019 (Windows) (Adobe Photoshop CC 2019 (Windows), ASCII, 34 components, 34 bytes)"
+            ),
+            "Adobe Photoshop CC 2019 (Windows)"
+        );
+        assert_eq!(
+            raw_value("AF-S 50mm (IF) (AF-S 50mm (IF), ASCII, 15 components, 15 bytes)"),
+            "AF-S 50mm (IF)"
+        );
+        assert_eq!(
+            raw_value("51/1 30/1 2604/100 (51, 30, 26.04, Rational, 3 components, 24 bytes)"),
+            "51/1 30/1 2604/100"
+        );
+        assert_eq!(
+            raw_value("1 (Top-left, Short, 1 components, 2 bytes)"),
+            "1"
+        );
+        assert_eq!(
+            raw_value("x (y, Short, many components, 2 bytes)"),
+            "x (y, Short, many components, 2 bytes)"
+        );
+        assert_eq!(
+            raw_value(""),
+            ""
+        );
+    }
+
+    #[test]
+    fn parse_rationals_skips_bad_items() {
+        assert_eq!(
+            parse_rationals("51/1 30/1 2604/100"),
+            vec![51.0, 30.0, 26.04]
+        );
+        assert_eq!(
+            parse_rationals("1/0 2/ /3 x/y 4"),
+            vec![4.0]
+        );
+        assert!(parse_rationals("").is_empty());
+    }
+
+    #[test]
+    fn degrees_rejects_malformed_values() {
+        assert_eq!(
+            degrees("51/1 30/1 0/1"),
+            Some(51.5)
+        );
+        assert_eq!(
+            degrees("51/1"),
+            Some(51.0)
+        );
+        assert_eq!(
+            degrees("51/1 0/0 30/1"),
+            None
+        );
+        assert_eq!(
+            degrees(""),
+            None
+        );
+        assert_eq!(
+            degrees("north"),
+            None
+        );
+    }
+
+    #[test]
+    fn exif_date_time_rejects_bad_dates() {
+        assert_eq!(
+            ExifDateTime::parse("2024:02:29 13:45:00"),
+            Some(
+                ExifDateTime {
+                    year: 2024,
+                    month: 2,
+                    day: 29,
+                    hour: 13,
+                    minute: 45,
+                    second: 0,
+                }
+            )
+        );
+        for text in [
+            "",
+            "2024:02:29",
+            "0000:00:00 00:00:00",
+            "    :  :     :  :  ",
+            "2024:13:01 00:00:00",
+            "2024:01:01 24:00:00",
+            "2024:01:01 12:00",
+            "99999:01:01 00:00:00",
+            "2024-01-01 00:00:00",
+            "2024:01:01 00:00:00:00",
+        ] {
+            assert_eq!(
+                ExifDateTime::parse(text),
+                None,
+                "{text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Watermarks and overlays
+//!
+//! `composite2` wants both images in its compositing space, with alpha on the overlay, and
+//! returns an image with alpha in that space whatever the base was. `VipsImage::overlay` does the
+//! conversions on both sides, so a logo with or without alpha can go on a grey, 16-bit or sRGB
+//! image and the result has the bands and interpretation of the base:
+//!
+//! ```no_run
+//! use libvips::{ops::BlendMode, overlay::Placement, VipsImage};
+//!
+//! let photo = VipsImage::new_from_file("photo.jpg").unwrap();
+//! let logo = VipsImage::new_from_file("logo.png").unwrap();
+//! let placement = Placement {
+//!     scale_relative_to_width: Some(0.2),
+//!     opacity: 0.7,
+//!     ..Placement::default()
+//! };
+//! let marked = photo.overlay(&logo, &placement, BlendMode::Over).unwrap();
+//! ```
+use crate::{
+    ops::{BandFormat, BlendMode, CompassDirection, Extend, Interpretation},
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+
+/// Where and how an overlay goes on an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub gravity: CompassDirection,
+    /// Distance from the edges, or the gap between tiles
+    pub margin: i32,
+    /// Resizes the overlay to this fraction of the width of the image
+    pub scale_relative_to_width: Option<f64>,
+    /// 0 to 1, multiplied into the alpha of the overlay
+    pub opacity: f64,
+    /// Repeats the overlay over the whole image, ignoring `gravity`
+    pub tile: bool,
+}
+
+impl Default for Placement {
+    /// Bottom right, 10 pixels in, at its own size and fully opaque
+    fn default() -> Placement {
+        Placement {
+            gravity: CompassDirection::SouthEast,
+            margin: 10,
+            scale_relative_to_width: None,
+            opacity: 1.0,
+            tile: false,
+        }
+    }
+}
+
+impl VipsImage {
+    /// Composites `overlay` on this image with `mode`, see [`Placement`]
+    pub fn overlay(
+        &self,
+        overlay: &VipsImage,
+        placement: &Placement,
+        mode: BlendMode,
+    ) -> Result<VipsImage> {
+        let layer = prepare(
+            overlay,
+            self.get_width(),
+            placement,
+        )?;
+        if placement.tile {
+            let cell = layer.embed_with_opts(
+                0,
+                0,
+                layer.get_width() + placement.margin,
+                layer.get_height() + placement.margin,
+                VOption::new().set(
+                    "extend",
+                    v_value!(Extend::Black as i32),
+                ),
+            )?;
+            let across = (self.get_width() + cell.get_width() - 1) / cell.get_width();
+            let down = (self.get_height() + cell.get_height() - 1) / cell.get_height();
+            let tiled = cell
+                .replicate(
+                    across,
+                    down,
+                )?
+                .extract_area(
+                    0,
+                    0,
+                    self.get_width(),
+                    self.get_height(),
+                )?;
+            return composite_at(
+                self,
+                &tiled,
+                0,
+                0,
+                mode,
+            );
+        }
+        let (x, y) = position(
+            placement.gravity,
+            (
+                self.get_width(),
+                self.get_height(),
+            ),
+            (
+                layer.get_width(),
+                layer.get_height(),
+            ),
+            placement.margin,
+        );
+        composite_at(
+            self,
+            &layer,
+            x,
+            y,
+            mode,
+        )
+    }
+}
+
+/// The overlay as 8-bit sRGB with alpha, scaled and faded as placed
+fn prepare(overlay: &VipsImage, base_width: i32, placement: &Placement) -> Result<VipsImage> {
+    let mut layer = overlay.colourspace(Interpretation::Srgb)?;
+    if !layer.image_hasalpha() {
+        layer = layer.bandjoin_const(&[255.0])?;
+    }
+    if let Some(fraction) = placement.scale_relative_to_width {
+        let width = (base_width as f64 * fraction)
+            .round()
+            .max(1.0);
+        // resize premultiplied, so transparent pixels do not bleed into the edges
+        layer = layer
+            .premultiply()?
+            .resize(width / layer.get_width() as f64)?
+            .unpremultiply()?
+            .cast(BandFormat::Uchar)?;
+    }
+    if placement.opacity < 1.0 {
+        layer = layer
+            .linear(
+                &[
+                    1.0,
+                    1.0,
+                    1.0,
+                    placement
+                        .opacity
+                        .max(0.0),
+                ],
+                &[0.0],
+            )?
+            .cast(BandFormat::Uchar)?;
+    }
+    Ok(layer)
+}
+
+/// The top left corner of an `inner` box placed at a compass position in an `outer` one
+pub(crate) fn position(
+    gravity: CompassDirection,
+    (outer_w, outer_h): (i32, i32),
+    (inner_w, inner_h): (i32, i32),
+    margin: i32,
+) -> (i32, i32) {
+    let left = margin;
+    let centre_x = (outer_w - inner_w) / 2;
+    let right = outer_w - inner_w - margin;
+    let top = margin;
+    let centre_y = (outer_h - inner_h) / 2;
+    let bottom = outer_h - inner_h - margin;
+    match gravity {
+        CompassDirection::North => (
+            centre_x,
+            top,
+        ),
+        CompassDirection::East => (
+            right,
+            centre_y,
+        ),
+        CompassDirection::South => (
+            centre_x,
+            bottom,
+        ),
+        CompassDirection::West => (
+            left,
+            centre_y,
+        ),
+        CompassDirection::NorthEast => (right, top),
+        CompassDirection::SouthEast => (
+            right,
+            bottom,
+        ),
+        CompassDirection::SouthWest => (
+            left,
+            bottom,
+        ),
+        CompassDirection::NorthWest => (left, top),
+        _ => (
+            centre_x,
+            centre_y,
+        ),
+    }
+}
+
+/// `composite2` of an sRGB layer with alpha over an image. The result has the bands of the
+/// image, and is converted back for grey and 16-bit images.
+pub(crate) fn composite_at(
+    base: &VipsImage,
+    layer: &VipsImage,
+    x: i32,
+    y: i32,
+    mode: BlendMode,
+) -> Result<VipsImage> {
+    let mut result = base.composite2_with_opts(
+        layer,
+        mode,
+        VOption::new()
+            .set(
+                "x",
+                v_value!(x),
+            )
+            .set(
+                "y",
+                v_value!(y),
+            ),
+    )?;
+    if !base.image_hasalpha() && result.image_hasalpha() {
+        // composite2 adds an alpha band; an opaque base stays opaque
+        result = result.extract_band_with_opts(
+            0,
+            VOption::new().set(
+                "n",
+                v_value!(result.get_bands() - 1),
+            ),
+        )?;
+    }
+    let interpretation = base.get_interpretation()?;
+    match interpretation {
+        Interpretation::BW | Interpretation::Grey16 | Interpretation::Rgb16
+            if result.get_interpretation()? != interpretation =>
+        {
+            result.colourspace(interpretation)
+        }
+        _ => Ok(result),
+    }
+}
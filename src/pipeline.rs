@@ -0,0 +1,638 @@
+//! Recorded operation pipelines
+//!
+//! A [`Pipeline`] is a list of steps, each naming a vips operation and its arguments.
+//! Every step takes the output image of the previous one, so a recipe such as
+//! autorot, thumbnail, sharpen and webp can be declared once and replayed on many inputs.
+//!
+//! Pipelines are validated against operation introspection when they are built or
+//! deserialized, so libvips must be initialized before loading one.
+//!
+//! ```no_run
+//! use libvips::pipeline::{Pipeline, Step};
+//!
+//! let pipeline = Pipeline::new(vec![
+//!     Step::new("autorot"),
+//!     Step::new("thumbnail_image").arg("width", 512),
+//!     Step::new("sharpen"),
+//!     Step::new("webpsave_buffer").arg("Q", 80),
+//! ])
+//! .unwrap();
+//! ```
+use crate::{
+    error::Error,
+    introspect::{Argument, ArgumentType, Operation},
+    utils,
+    voption::{call, VOption, VipsValue},
+    Result, VipsBlob, VipsImage,
+};
+use std::collections::BTreeMap;
+use std::ptr::null_mut;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The value of a step argument
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
+pub enum Value {
+    Bool(bool),
+    Int(i32),
+    Uint(u64),
+    Double(f64),
+    Str(String),
+    IntArray(Vec<i32>),
+    DoubleArray(Vec<f64>),
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Uint(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Double(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<&[i32]> for Value {
+    fn from(value: &[i32]) -> Self {
+        Value::IntArray(value.to_vec())
+    }
+}
+
+impl From<Vec<i32>> for Value {
+    fn from(value: Vec<i32>) -> Self {
+        Value::IntArray(value)
+    }
+}
+
+impl From<&[f64]> for Value {
+    fn from(value: &[f64]) -> Self {
+        Value::DoubleArray(value.to_vec())
+    }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(value: Vec<f64>) -> Self {
+        Value::DoubleArray(value)
+    }
+}
+
+impl Value {
+    /// Parses a command-line style string for the given argument, e.g. `centre`, `3.5` or `255 0 0`
+    pub fn parse(arg: &Argument, text: &str) -> Result<Value> {
+        let invalid = || {
+            Error::OperationErrorExt(format!(
+                "Argument `{}`: cannot parse `{}`",
+                arg.name, text
+            ))
+        };
+        let items = || {
+            text.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|item| !item.is_empty())
+        };
+        let value = match arg.kind {
+            ArgumentType::Bool => match text {
+                "true" | "yes" | "on" | "1" => Value::Bool(true),
+                "false" | "no" | "off" | "0" => Value::Bool(false),
+                _ => return Err(invalid()),
+            },
+            ArgumentType::Int => Value::Int(
+                text.parse()
+                    .map_err(|_| invalid())?,
+            ),
+            ArgumentType::Uint64 => Value::Uint(
+                text.parse()
+                    .map_err(|_| invalid())?,
+            ),
+            ArgumentType::Double => Value::Double(
+                text.parse()
+                    .map_err(|_| invalid())?,
+            ),
+            ArgumentType::IntArray => Value::IntArray(
+                items()
+                    .map(str::parse)
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            ArgumentType::DoubleArray => Value::DoubleArray(
+                items()
+                    .map(str::parse)
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            _ => Value::Str(text.to_string()),
+        };
+        coerce(
+            arg,
+            &value,
+        )
+        .map_err(
+            |message| {
+                Error::OperationErrorExt(format!(
+                    "Argument `{}`: {}",
+                    arg.name, message
+                ))
+            },
+        )
+    }
+
+    pub fn as_vips_value(&self) -> VipsValue<'_> {
+        match self {
+            Value::Bool(value) => VipsValue::Bool(*value),
+            Value::Int(value) => VipsValue::Int(*value),
+            Value::Uint(value) => VipsValue::Uint(*value),
+            Value::Double(value) => VipsValue::Double(*value),
+            Value::Str(value) => VipsValue::Str(value),
+            Value::IntArray(value) => VipsValue::IntArray(value),
+            Value::DoubleArray(value) => VipsValue::DoubleArray(value),
+        }
+    }
+}
+
+/// One operation of a pipeline, by nickname, with its arguments
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Step {
+    pub nickname: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub args: BTreeMap<String, Value>,
+}
+
+impl Step {
+    pub fn new(nickname: &str) -> Self {
+        Self {
+            nickname: nickname.to_string(),
+            args: BTreeMap::new(),
+        }
+    }
+
+    /// Sets an argument of the step
+    pub fn arg(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.args
+            .insert(
+                name.to_string(),
+                value.into(),
+            );
+        self
+    }
+
+    /// Returns the arguments of the step as VOption
+    pub fn options(&self) -> VOption<'_> {
+        let mut option = VOption::new();
+        for (name, value) in &self.args {
+            option.add(
+                name,
+                value.as_vips_value(),
+            );
+        }
+        option
+    }
+}
+
+/// The result of running a pipeline
+pub enum PipelineOutput {
+    /// The last step produced an image
+    Image(VipsImage),
+    /// The last step was a `*save_buffer` operation
+    Buffer(Vec<u8>),
+    /// The last step saved to a file or target
+    Saved,
+}
+
+#[derive(Debug, Clone)]
+enum Sink {
+    Image(String),
+    Buffer(String),
+    None,
+}
+
+#[derive(Debug, Clone)]
+struct Plan {
+    input: String,
+    sink: Sink,
+}
+
+/// A validated list of steps
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "PipelineSpec", into = "PipelineSpec")
+)]
+pub struct Pipeline {
+    steps: Vec<Step>,
+    plans: Vec<Plan>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct PipelineSpec {
+    steps: Vec<Step>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PipelineSpec> for Pipeline {
+    type Error = Error;
+
+    fn try_from(spec: PipelineSpec) -> Result<Self> {
+        Pipeline::new(spec.steps)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Pipeline> for PipelineSpec {
+    fn from(pipeline: Pipeline) -> Self {
+        PipelineSpec {
+            steps: pipeline.steps,
+        }
+    }
+}
+
+impl Pipeline {
+    /// Validates the steps against operation introspection and builds the pipeline
+    pub fn new(mut steps: Vec<Step>) -> Result<Pipeline> {
+        if steps.is_empty() {
+            return Err(Error::OperationError("Pipeline has no steps"));
+        }
+
+        let mut plans = Vec::with_capacity(steps.len());
+        let last = steps
+            .len()
+            .saturating_sub(1);
+        for (index, step) in steps
+            .iter_mut()
+            .enumerate()
+        {
+            let plan = validate_step(
+                index, step,
+            )?;
+            if index != last
+                && !matches!(
+                    plan.sink,
+                    Sink::Image(_)
+                )
+            {
+                return Err(
+                    step_error(
+                        index,
+                        step,
+                        "does not produce an image, so it must be the last step",
+                    ),
+                );
+            }
+            plans.push(plan);
+        }
+
+        Ok(Pipeline {
+            steps,
+            plans,
+        })
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Loads and validates a pipeline from JSON
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Pipeline> {
+        serde_json::from_str(json).map_err(|e| Error::OperationErrorExt(e.to_string()))
+    }
+
+    /// Serializes the pipeline to JSON
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| Error::OperationErrorExt(e.to_string()))
+    }
+
+    /// Runs every step on the input image
+    pub fn run(&self, image: &VipsImage) -> Result<PipelineOutput> {
+        let mut current: Option<VipsImage> = None;
+        for (index, (step, plan)) in self
+            .steps
+            .iter()
+            .zip(
+                self.plans
+                    .iter(),
+            )
+            .enumerate()
+        {
+            let mut out_out = VipsImage::from(null_mut());
+            let mut buffer_out = VipsBlob::from(null_mut());
+            let mut option = step
+                .options()
+                .set(
+                    &plan.input,
+                    VipsValue::Image(
+                        current
+                            .as_ref()
+                            .unwrap_or(image),
+                    ),
+                );
+            match &plan.sink {
+                Sink::Image(name) => option.add(
+                    name,
+                    VipsValue::MutImage(&mut out_out),
+                ),
+                Sink::Buffer(name) => option.add(
+                    name,
+                    VipsValue::MutBlob(&mut buffer_out),
+                ),
+                Sink::None => {}
+            }
+
+            let vips_op_response = call(
+                &step.nickname,
+                option,
+            );
+            utils::result(
+                vips_op_response,
+                (),
+                step_error(
+                    index,
+                    step,
+                    "failed",
+                ),
+            )?;
+
+            match plan.sink {
+                Sink::Image(_) => current = Some(out_out),
+                Sink::Buffer(_) => return Ok(PipelineOutput::Buffer(buffer_out.into())),
+                Sink::None => return Ok(PipelineOutput::Saved),
+            }
+        }
+
+        current
+            .map(PipelineOutput::Image)
+            .ok_or(Error::OperationError("Pipeline has no steps"))
+    }
+
+    /// Runs the pipeline and returns the final image
+    pub fn apply(&self, image: &VipsImage) -> Result<VipsImage> {
+        match self.run(image)? {
+            PipelineOutput::Image(image) => Ok(image),
+            _ => Err(Error::OperationError("Pipeline does not end with an image")),
+        }
+    }
+
+    /// Runs a pipeline ending with a `*save_buffer` step and returns the encoded bytes
+    pub fn apply_to_buffer(&self, image: &VipsImage) -> Result<Vec<u8>> {
+        match self.run(image)? {
+            PipelineOutput::Buffer(buffer) => Ok(buffer),
+            _ => Err(Error::OperationError("Pipeline does not end with a buffer")),
+        }
+    }
+}
+
+fn step_error(index: usize, step: &Step, message: &str) -> Error {
+    Error::OperationErrorExt(format!(
+        "Pipeline step {} (`{}`) {}",
+        index, step.nickname, message
+    ))
+}
+
+fn validate_step(index: usize, step: &mut Step) -> Result<Plan> {
+    let operation = Operation::find(&step.nickname).map_err(|_| {
+        step_error(
+            index,
+            step,
+            "is not a known operation",
+        )
+    })?;
+
+    if operation
+        .arguments
+        .iter()
+        .any(|arg| arg.modify)
+    {
+        return Err(
+            step_error(
+                index,
+                step,
+                "modifies its input in place and cannot be used in a pipeline",
+            ),
+        );
+    }
+
+    let input = operation
+        .required_inputs()
+        .find(|arg| arg.kind == ArgumentType::Image)
+        .map(|arg| {
+            arg.name
+                .clone()
+        })
+        .ok_or_else(|| {
+            step_error(
+                index,
+                step,
+                "does not take an input image",
+            )
+        })?;
+
+    let sink = if let Some(arg) = operation
+        .arguments
+        .iter()
+        .find(|arg| arg.required && arg.output && arg.kind == ArgumentType::Image)
+    {
+        Sink::Image(
+            arg.name
+                .clone(),
+        )
+    } else if let Some(arg) = operation
+        .arguments
+        .iter()
+        .find(|arg| arg.required && arg.output && arg.kind == ArgumentType::Blob)
+    {
+        Sink::Buffer(
+            arg.name
+                .clone(),
+        )
+    } else {
+        Sink::None
+    };
+
+    let nickname = step
+        .nickname
+        .clone();
+    for (name, value) in step
+        .args
+        .iter_mut()
+    {
+        let arg = match operation.argument(name) {
+            Some(arg) if arg.input && *name != input => arg,
+            _ => {
+                return Err(
+                    Error::OperationErrorExt(format!(
+                        "Pipeline step {} (`{}`) has no input argument `{}`",
+                        index, nickname, name
+                    )),
+                )
+            }
+        };
+        *value = coerce(arg, value).map_err(
+            |message| {
+                Error::OperationErrorExt(format!(
+                    "Pipeline step {} (`{}`) argument `{}`: {}",
+                    index, nickname, name, message
+                ))
+            },
+        )?;
+    }
+
+    if let Some(arg) = operation
+        .required_inputs()
+        .find(|arg| {
+            arg.name != input
+                && !step
+                    .args
+                    .contains_key(&arg.name)
+        })
+    {
+        return Err(
+            Error::OperationErrorExt(format!(
+                "Pipeline step {} (`{}`) is missing required argument `{}`",
+                index, nickname, arg.name
+            )),
+        );
+    }
+
+    Ok(Plan {
+        input,
+        sink,
+    })
+}
+
+/// Checks a value against the argument type, converting it where vips would accept it
+fn coerce(arg: &Argument, value: &Value) -> std::result::Result<Value, String> {
+    match (
+        &arg.kind,
+        value,
+    ) {
+        (ArgumentType::Bool, Value::Bool(_))
+        | (ArgumentType::Int, Value::Int(_))
+        | (ArgumentType::Uint64, Value::Uint(_))
+        | (ArgumentType::Double, Value::Double(_))
+        | (ArgumentType::String, Value::Str(_))
+        | (ArgumentType::IntArray, Value::IntArray(_))
+        | (ArgumentType::DoubleArray, Value::DoubleArray(_))
+        | (
+            ArgumentType::Flags {
+                ..
+            },
+            Value::Int(_),
+        ) => Ok(value.clone()),
+        (ArgumentType::Uint64, Value::Int(v)) if *v >= 0 => Ok(Value::Uint(*v as u64)),
+        (ArgumentType::Double, Value::Int(v)) => Ok(Value::Double(*v as f64)),
+        (ArgumentType::IntArray, Value::Int(v)) => Ok(Value::IntArray(vec![*v])),
+        (ArgumentType::DoubleArray, Value::Int(v)) => Ok(
+            Value::DoubleArray(vec![
+                *v as f64,
+            ]),
+        ),
+        (ArgumentType::DoubleArray, Value::Double(v)) => Ok(Value::DoubleArray(vec![*v])),
+        (ArgumentType::DoubleArray, Value::IntArray(v)) => Ok(
+            Value::DoubleArray(
+                v.iter()
+                    .map(|v| *v as f64)
+                    .collect(),
+            ),
+        ),
+        (
+            ArgumentType::Enum {
+                nicks,
+                ..
+            },
+            Value::Str(nick),
+        ) => {
+            if nicks.contains(nick) {
+                Ok(value.clone())
+            } else {
+                Err(format!(
+                    "`{}` is not one of {}",
+                    nick,
+                    nicks.join(", ")
+                ))
+            }
+        }
+        (
+            ArgumentType::Flags {
+                name,
+                nicks,
+            },
+            Value::Str(names),
+        ) => {
+            // vips only converts enum nicks for us, so flags are resolved to their int value here
+            if let Some(nick) = names
+                .split(['|', ',', ' '])
+                .filter(|nick| !nick.is_empty())
+                .find(|nick| {
+                    !nicks
+                        .iter()
+                        .any(|n| n == nick)
+                })
+            {
+                return Err(format!(
+                    "`{}` is not one of {}",
+                    nick,
+                    nicks.join(", ")
+                ));
+            }
+            flags_from_nick(
+                name, names,
+            )
+            .map(Value::Int)
+        }
+        (kind, _) => Err(format!(
+            "expects {:?}, got {:?}",
+            kind, value
+        )),
+    }
+}
+
+fn flags_from_nick(type_name: &str, nicks: &str) -> std::result::Result<i32, String> {
+    let domain = utils::new_c_string("pipeline").map_err(|e| e.to_string())?;
+    let nicks = utils::new_c_string(nicks).map_err(|e| e.to_string())?;
+    let value = unsafe {
+        crate::bindings::vips_flags_from_nick(
+            domain.as_ptr(),
+            utils::get_g_type(type_name),
+            nicks.as_ptr(),
+        )
+    };
+    if value < 0 {
+        unsafe { crate::bindings::vips_error_clear() };
+        Err(format!(
+            "cannot parse flags `{}`",
+            nicks.to_string_lossy()
+        ))
+    } else {
+        Ok(value)
+    }
+}
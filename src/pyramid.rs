@@ -0,0 +1,484 @@
+//! Tile pyramids
+//!
+//! `dzsave` writes a whole pyramid to a directory tree or an archive. [`TilePyramid`] makes the
+//! same tiles but hands each one to a callback as soon as it is encoded, so it can go straight to
+//! object storage without temporary files. Large levels are cut from the lazy source, and only
+//! levels of at most 16 tiles are rendered to memory:
+//!
+//! ```no_run
+//! use libvips::{encode::Encoder, encode::OutputFormat, pyramid::{Layout, TilePyramid}, VipsImage};
+//!
+//! let image = VipsImage::new_from_file("huge.tif").unwrap();
+//! let pyramid = TilePyramid::new(Layout::DeepZoom).encoder(Encoder::from(OutputFormat::Webp));
+//! pyramid
+//!     .build(&image, |tile| {
+//!         println!("{} ({} bytes)", tile.path, tile.data.len());
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+use crate::{
+    encode::{Encoder, OutputFormat},
+    error::Error,
+    ops::Extend,
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+
+/// Levels up to this many tiles are rendered to memory, larger ones stay lazy
+const MEMORY_TILES: i64 = 16;
+
+/// How levels are numbered and tiles are named
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// `name_files/level/column_row.jpg` and `name.dzi`, down to a 1x1 level
+    DeepZoom,
+    /// `TileGroupN/level-column-row.jpg` and `ImageProperties.xml`
+    Zoomify,
+    /// `level/row/column.jpg` with every tile padded to full size
+    Google,
+    /// IIIF Image API level 0 paths, `x,y,w,h/w,h/0/default.jpg`, and `info.json`
+    Iiif,
+}
+
+/// One encoded tile
+#[derive(Debug, Clone)]
+pub struct Tile {
+    /// 0 is the smallest level
+    pub level: i32,
+    pub column: i32,
+    pub row: i32,
+    /// The path of the tile in the layout, relative to the pyramid root
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// Builds the tiles of a pyramid, see the module docs
+#[derive(Debug, Clone)]
+pub struct TilePyramid {
+    layout: Layout,
+    tile_size: i32,
+    overlap: i32,
+    encoder: Encoder,
+    name: String,
+    background: Vec<f64>,
+}
+
+/// The size of one level, with the shrink factor from the full image
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    level: i32,
+    shrink: i32,
+    width: i32,
+    height: i32,
+    /// The size of the full image
+    full_width: i32,
+    full_height: i32,
+}
+
+impl TilePyramid {
+    /// The defaults of `dzsave` for a layout: 254 pixel tiles with 1 pixel overlap for Deep Zoom,
+    /// 256 pixel tiles without overlap otherwise, as JPEG
+    pub fn new(layout: Layout) -> TilePyramid {
+        let (tile_size, overlap) = match layout {
+            Layout::DeepZoom => (254, 1),
+            _ => (256, 0),
+        };
+        TilePyramid {
+            layout,
+            tile_size,
+            overlap,
+            encoder: Encoder::from(OutputFormat::Jpeg),
+            name: "image".to_string(),
+            background: vec![255.0],
+        }
+    }
+
+    pub fn tile_size(mut self, tile_size: i32) -> TilePyramid {
+        self.tile_size = tile_size;
+        self
+    }
+
+    /// Pixels each tile shares with its neighbours. Only Deep Zoom uses overlap.
+    pub fn overlap(mut self, overlap: i32) -> TilePyramid {
+        self.overlap = overlap;
+        self
+    }
+
+    /// The tile format and its options, which also set the tile suffix
+    pub fn encoder(mut self, encoder: Encoder) -> TilePyramid {
+        self.encoder = encoder;
+        self
+    }
+
+    /// The Deep Zoom base name, or the IIIF `id`
+    pub fn name(mut self, name: &str) -> TilePyramid {
+        self.name = name.to_string();
+        self
+    }
+
+    /// The colour Google tiles are padded with
+    pub fn background(mut self, background: &[f64]) -> TilePyramid {
+        self.background = background.to_vec();
+        self
+    }
+
+    /// The tile file suffix without the dot, e.g. `jpg`
+    pub fn suffix(&self) -> &'static str {
+        self.encoder
+            .format()
+            .image_format()
+            .suffix()
+            .unwrap_or(".bin")
+            .trim_start_matches('.')
+    }
+
+    /// The number of levels of a pyramid for an image of this size
+    pub fn level_count(&self, width: i32, height: i32) -> i32 {
+        self.levels(
+            width,
+            height,
+        )
+        .len() as i32
+    }
+
+    /// The descriptor file name and contents for an image of this size: `name.dzi`,
+    /// `ImageProperties.xml` or `info.json`. Google pyramids have none.
+    pub fn descriptor(
+        &self,
+        width: i32,
+        height: i32,
+    ) -> Option<(
+        String,
+        String,
+    )> {
+        match self.layout {
+            Layout::DeepZoom => Some((
+                format!(
+                    "{}.dzi",
+                    self.name
+                ),
+                format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"{}\" Overlap=\"{}\" TileSize=\"{}\">\n  \
+                     <Size Height=\"{}\" Width=\"{}\"/>\n\
+                     </Image>\n",
+                    self.suffix(),
+                    self.overlap,
+                    self.tile_size,
+                    height,
+                    width
+                ),
+            )),
+            Layout::Zoomify => {
+                let tiles: i32 = self
+                    .levels(
+                        width, height,
+                    )
+                    .iter()
+                    .map(|level| self.columns(level) * self.rows(level))
+                    .sum();
+                Some((
+                    "ImageProperties.xml".to_string(),
+                    format!(
+                        "<IMAGE_PROPERTIES WIDTH=\"{}\" HEIGHT=\"{}\" NUMTILES=\"{}\" NUMIMAGES=\"1\" VERSION=\"1.8\" TILESIZE=\"{}\" />\n",
+                        width, height, tiles, self.tile_size
+                    ),
+                ))
+            }
+            Layout::Google => None,
+            Layout::Iiif => {
+                let factors: Vec<String> = self
+                    .levels(
+                        width, height,
+                    )
+                    .iter()
+                    .map(|level| level.shrink.to_string())
+                    .collect();
+                Some((
+                    "info.json".to_string(),
+                    format!(
+                        "{{\n  \"@context\": \"http://iiif.io/api/image/3/context.json\",\n  \
+                         \"id\": \"{}\",\n  \"type\": \"ImageService3\",\n  \
+                         \"protocol\": \"http://iiif.io/api/image\",\n  \"profile\": \"level0\",\n  \
+                         \"width\": {},\n  \"height\": {},\n  \
+                         \"tiles\": [{{ \"width\": {}, \"scaleFactors\": [{}] }}]\n}}\n",
+                        json_escape(&self.name),
+                        width,
+                        height,
+                        self.tile_size,
+                        factors.join(", ")
+                    ),
+                ))
+            }
+        }
+    }
+
+    /// Encodes every tile and passes it to `write`, largest level first. Each level is shrunk
+    /// from the one before. Levels larger than 16 tiles stay lazy, so their tiles are computed
+    /// from the input as they are cut; smaller ones are rendered to memory once. Apart from the
+    /// caches of libvips, memory use is therefore bounded by 16 tiles plus one encoded tile. The
+    /// first error from `write` stops the build and is returned.
+    pub fn build<F>(&self, image: &VipsImage, mut write: F) -> Result<()>
+    where
+        F: FnMut(Tile) -> Result<()>,
+    {
+        if self.tile_size <= 0 || self.overlap < 0 || 2 * self.overlap >= self.tile_size {
+            return Err(Error::OperationError("Bad tile size or overlap"));
+        }
+        let levels = self.levels(
+            image.get_width(),
+            image.get_height(),
+        );
+        // Zoomify groups tiles 256 to a directory, counting from the smallest level
+        let mut first_tile: Vec<i32> = levels
+            .iter()
+            .rev()
+            .scan(
+                0,
+                |count, level| {
+                    let first = *count;
+                    *count += self.columns(level) * self.rows(level);
+                    Some(first)
+                },
+            )
+            .collect();
+        first_tile.reverse();
+
+        let mut current: Option<VipsImage> = None;
+        for (level, first) in levels
+            .iter()
+            .zip(first_tile)
+        {
+            if level.shrink > 1 {
+                let shrunk = shrink_to(
+                    current
+                        .as_ref()
+                        .unwrap_or(image),
+                    level.width,
+                    level.height,
+                )?;
+                current = Some(
+                    if self.fits_in_memory(level) {
+                        VipsImage::image_copy_memory(shrunk)?
+                    } else {
+                        shrunk
+                    },
+                );
+            }
+            let source = current
+                .as_ref()
+                .unwrap_or(image);
+            for row in 0..self.rows(level) {
+                for column in 0..self.columns(level) {
+                    let index = first + row * self.columns(level) + column;
+                    write(self.tile(
+                        source,
+                        level,
+                        column,
+                        row,
+                        index,
+                    )?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The levels from the full image down, each half the size of the one before, rounding up
+    fn levels(&self, width: i32, height: i32) -> Vec<Level> {
+        let mut sizes = vec![(
+            width.max(1),
+            height.max(1),
+        )];
+        loop {
+            let (w, h) = *sizes
+                .last()
+                .unwrap();
+            let done = match self.layout {
+                Layout::DeepZoom => w == 1 && h == 1,
+                _ => w <= self.tile_size && h <= self.tile_size,
+            };
+            if done || self.tile_size <= 0 {
+                break;
+            }
+            sizes.push((
+                (w + 1) / 2,
+                (h + 1) / 2,
+            ));
+        }
+        let top = sizes.len() as i32 - 1;
+        sizes
+            .into_iter()
+            .enumerate()
+            .map(
+                |(k, (w, h))| Level {
+                    level: top - k as i32,
+                    shrink: 1 << k,
+                    width: w,
+                    height: h,
+                    full_width: width.max(1),
+                    full_height: height.max(1),
+                },
+            )
+            .collect()
+    }
+
+    /// Whether a level is small enough to render to memory, see `build`
+    fn fits_in_memory(&self, level: &Level) -> bool {
+        let tile_area = self.tile_size as i64 * self.tile_size as i64;
+        level.width as i64 * level.height as i64 <= MEMORY_TILES * tile_area
+    }
+
+    fn columns(&self, level: &Level) -> i32 {
+        (level.width + self.tile_size - 1) / self.tile_size
+    }
+
+    fn rows(&self, level: &Level) -> i32 {
+        (level.height + self.tile_size - 1) / self.tile_size
+    }
+
+    fn tile(
+        &self,
+        source: &VipsImage,
+        level: &Level,
+        column: i32,
+        row: i32,
+        index: i32,
+    ) -> Result<Tile> {
+        let overlap = if self.layout == Layout::DeepZoom { self.overlap } else { 0 };
+        let left = (column * self.tile_size - overlap).max(0);
+        let top = (row * self.tile_size - overlap).max(0);
+        let right = ((column + 1) * self.tile_size + overlap).min(level.width);
+        let bottom = ((row + 1) * self.tile_size + overlap).min(level.height);
+        let mut tile = source.extract_area(
+            left,
+            top,
+            right - left,
+            bottom - top,
+        )?;
+        if self.layout == Layout::Google
+            && (tile.get_width() < self.tile_size || tile.get_height() < self.tile_size)
+        {
+            tile = tile.embed_with_opts(
+                0,
+                0,
+                self.tile_size,
+                self.tile_size,
+                VOption::new()
+                    .set(
+                        "extend",
+                        v_value!(Extend::Background as i32),
+                    )
+                    .set(
+                        "background",
+                        v_value!(self
+                            .background
+                            .as_slice()),
+                    ),
+            )?;
+        }
+        let suffix = self.suffix();
+        let path = match self.layout {
+            Layout::DeepZoom => format!(
+                "{}_files/{}/{}_{}.{}",
+                self.name, level.level, column, row, suffix
+            ),
+            Layout::Zoomify => format!(
+                "TileGroup{}/{}-{}-{}.{}",
+                index / 256,
+                level.level,
+                column,
+                row,
+                suffix
+            ),
+            Layout::Google => format!(
+                "{}/{}/{}.{}",
+                level.level, row, column, suffix
+            ),
+            Layout::Iiif => {
+                // the region is in full image coordinates
+                let span = self.tile_size * level.shrink;
+                let (x, y) = (
+                    column * span,
+                    row * span,
+                );
+                let full_width = level
+                    .full_width
+                    .min(x + span)
+                    - x;
+                let full_height = level
+                    .full_height
+                    .min(y + span)
+                    - y;
+                format!(
+                    "{},{},{},{}/{},{}/0/default.{}",
+                    x,
+                    y,
+                    full_width,
+                    full_height,
+                    tile.get_width(),
+                    tile.get_height(),
+                    suffix
+                )
+            }
+        };
+        Ok(Tile {
+            level: level.level,
+            column,
+            row,
+            path,
+            data: tile.save_buffer(&self.encoder)?,
+        })
+    }
+}
+
+/// Resizes to exactly this size. `resize` rounds, so a pixel short is copied from the edge and a
+/// pixel over is cropped.
+fn shrink_to(image: &VipsImage, width: i32, height: i32) -> Result<VipsImage> {
+    let mut resized = image.resize_with_opts(
+        width as f64 / image.get_width() as f64,
+        VOption::new().set(
+            "vscale",
+            v_value!(height as f64 / image.get_height() as f64),
+        ),
+    )?;
+    if resized.get_width() != width || resized.get_height() != height {
+        resized = resized
+            .extract_area(
+                0,
+                0,
+                width.min(resized.get_width()),
+                height.min(resized.get_height()),
+            )?
+            .embed_with_opts(
+                0,
+                0,
+                width,
+                height,
+                VOption::new().set(
+                    "extend",
+                    v_value!(Extend::Copy as i32),
+                ),
+            )?;
+    }
+    Ok(resized)
+}
+
+/// Escapes a string for a JSON string literal
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!(
+                "\\u{:04x}",
+                c as u32
+            )),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,276 @@
+//! Resizing with fit modes
+//!
+//! [`Resize`] describes a target box the way sharp does, and works out the `thumbnail` size,
+//! crop and padding which give that result:
+//!
+//! - `Cover` fills the box and crops what overflows, at a gravity or the most interesting part
+//! - `Contain` fits inside the box and pads the rest with the background
+//! - `Fill` stretches to the box, ignoring the aspect ratio
+//! - `Inside` fits inside the box, so one side may be smaller
+//! - `Outside` covers the box without cropping, so one side may be larger
+//!
+//! ```no_run
+//! use libvips::resize::{Fit, Resize};
+//!
+//! let resize = Resize {
+//!     fit: Fit::Contain,
+//!     background: vec![255.0, 255.0, 255.0],
+//!     ..Resize::new(Some(300), Some(200))
+//! };
+//! let thumbnail = resize.apply_file("photo.jpg").unwrap();
+//! ```
+use crate::{
+    error::Error,
+    ops::{CompassDirection, Extend, Interesting, Size},
+    overlay::position,
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+
+/// How the image fills the box
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    Cover,
+    Contain,
+    Fill,
+    Inside,
+    Outside,
+}
+
+/// Which part of the image `Cover` keeps, or where `Contain` puts it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    Gravity(CompassDirection),
+    /// As `smartcrop`. `Contain` centres the image.
+    Interesting(Interesting),
+}
+
+/// A target size and fit, see the module docs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resize {
+    /// With only one side set the other follows the aspect ratio, and the fit does not matter
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub fit: Fit,
+    pub position: Position,
+    /// The padding colour for `Contain`. Missing alpha values are opaque.
+    pub background: Vec<f64>,
+    /// Never scales up. `Cover` then crops to at most the box and `Contain` still pads to it.
+    pub without_enlargement: bool,
+}
+
+impl Resize {
+    /// Covers the box, cropping at the centre, like sharp
+    pub fn new(width: Option<i32>, height: Option<i32>) -> Resize {
+        Resize {
+            width,
+            height,
+            fit: Fit::Cover,
+            position: Position::Gravity(CompassDirection::Centre),
+            background: vec![0.0, 0.0, 0.0],
+            without_enlargement: false,
+        }
+    }
+
+    /// The size an image of this size is scaled to before any crop or padding
+    pub fn scaled_size(&self, width: i32, height: i32) -> Result<(i32, i32)> {
+        let (iw, ih) = (
+            width as f64,
+            height as f64,
+        );
+        if self
+            .width
+            .is_some_and(|w| w <= 0)
+            || self
+                .height
+                .is_some_and(|h| h <= 0)
+        {
+            return Err(Error::OperationError("Resize width and height must be positive"));
+        }
+        let (mut sx, mut sy) = match (
+            self.width,
+            self.height,
+        ) {
+            (None, None) => return Err(Error::OperationError("Resize needs a width or a height")),
+            (Some(w), None) => (
+                w as f64 / iw,
+                w as f64 / iw,
+            ),
+            (None, Some(h)) => (
+                h as f64 / ih,
+                h as f64 / ih,
+            ),
+            (Some(w), Some(h)) => {
+                let (sx, sy) = (
+                    w as f64 / iw,
+                    h as f64 / ih,
+                );
+                match self.fit {
+                    Fit::Cover | Fit::Outside => (
+                        sx.max(sy),
+                        sx.max(sy),
+                    ),
+                    Fit::Contain | Fit::Inside => (
+                        sx.min(sy),
+                        sx.min(sy),
+                    ),
+                    Fit::Fill => (sx, sy),
+                }
+            }
+        };
+        if self.without_enlargement {
+            sx = sx.min(1.0);
+            sy = sy.min(1.0);
+        }
+        Ok((
+            ((iw * sx).round() as i32).max(1),
+            ((ih * sy).round() as i32).max(1),
+        ))
+    }
+
+    /// Resizes an image in memory, or one already being processed. The image is turned upright
+    /// first, as `thumbnail_image` does.
+    pub fn apply(&self, image: &VipsImage) -> Result<VipsImage> {
+        let (width, height) = self.upright_scaled_size(image)?;
+        let scaled = image.thumbnail_image_with_opts(
+            width,
+            self.thumbnail_options(height),
+        )?;
+        self.finish(scaled)
+    }
+
+    /// Resizes a file, shrinking on load where the format allows. The image is turned upright
+    /// first, as `thumbnail` does.
+    pub fn apply_file(&self, filename: &str) -> Result<VipsImage> {
+        let (width, height) = self.upright_scaled_size(&VipsImage::new_from_file(filename)?)?;
+        let scaled = VipsImage::thumbnail_with_opts(
+            filename,
+            width,
+            self.thumbnail_options(height),
+        )?;
+        self.finish(scaled)
+    }
+
+    /// Resizes an encoded image, shrinking on load where the format allows
+    pub fn apply_buffer(&self, buffer: &[u8]) -> Result<VipsImage> {
+        let (width, height) = self.upright_scaled_size(&VipsImage::new_from_buffer(buffer, "")?)?;
+        let scaled = VipsImage::thumbnail_buffer_with_opts(
+            buffer,
+            width,
+            self.thumbnail_options(height),
+        )?;
+        self.finish(scaled)
+    }
+
+    /// `scaled_size` of an image header, swapping the sides for orientations which rotate by 90
+    fn upright_scaled_size(&self, header: &VipsImage) -> Result<(i32, i32)> {
+        if (5..=8).contains(&header.get_orientation()) {
+            self.scaled_size(
+                header.get_height(),
+                header.get_width(),
+            )
+        } else {
+            self.scaled_size(
+                header.get_width(),
+                header.get_height(),
+            )
+        }
+    }
+
+    fn thumbnail_options(&self, height: i32) -> VOption<'_> {
+        VOption::new()
+            .set(
+                "height",
+                v_value!(height),
+            )
+            .set(
+                "size",
+                v_value!(Size::Force as i32),
+            )
+    }
+
+    /// Crops or pads the scaled image to the box
+    fn finish(&self, scaled: VipsImage) -> Result<VipsImage> {
+        let (Some(width), Some(height)) = (
+            self.width,
+            self.height,
+        ) else {
+            return Ok(scaled);
+        };
+        let size = (
+            scaled.get_width(),
+            scaled.get_height(),
+        );
+        match self.fit {
+            Fit::Cover if size.0 > width || size.1 > height => {
+                let crop = (
+                    width.min(size.0),
+                    height.min(size.1),
+                );
+                match self.position {
+                    Position::Interesting(interesting) => scaled.smartcrop_with_opts(
+                        crop.0,
+                        crop.1,
+                        VOption::new().set(
+                            "interesting",
+                            v_value!(interesting as i32),
+                        ),
+                    ),
+                    Position::Gravity(gravity) => {
+                        let (x, y) = position(
+                            gravity,
+                            size,
+                            crop,
+                            0,
+                        );
+                        scaled.extract_area(
+                            x,
+                            y,
+                            crop.0,
+                            crop.1,
+                        )
+                    }
+                }
+            }
+            Fit::Contain if size.0 < width || size.1 < height => {
+                let gravity = match self.position {
+                    Position::Gravity(gravity) => gravity,
+                    Position::Interesting(_) => CompassDirection::Centre,
+                };
+                let (x, y) = position(
+                    gravity,
+                    (
+                        width,
+                        height,
+                    ),
+                    size,
+                    0,
+                );
+                let mut background = self
+                    .background
+                    .clone();
+                background.resize(
+                    scaled.get_bands() as usize,
+                    255.0,
+                );
+                scaled.embed_with_opts(
+                    x,
+                    y,
+                    width,
+                    height,
+                    VOption::new()
+                        .set(
+                            "extend",
+                            v_value!(Extend::Background as i32),
+                        )
+                        .set(
+                            "background",
+                            v_value!(background.as_slice()),
+                        ),
+                )
+            }
+            _ => Ok(scaled),
+        }
+    }
+}
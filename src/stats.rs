@@ -0,0 +1,332 @@
+//! Image statistics
+//!
+//! Typed views of the matrices libvips statistics operations return: [`ImageStats`] for `stats`
+//! and [`Histogram`] for the `hist_*` operations.
+use crate::{error::Error, ops::BandFormat, Result, VipsImage};
+
+/// Statistics of one band, or of all bands together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandStats {
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub sum_squares: f64,
+    pub mean: f64,
+    /// Standard deviation
+    pub deviation: f64,
+    /// Position of the first minimum
+    pub min_x: i32,
+    pub min_y: i32,
+    /// Position of the first maximum
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl BandStats {
+    fn from_row(row: &[f64]) -> BandStats {
+        BandStats {
+            min: row[0],
+            max: row[1],
+            sum: row[2],
+            sum_squares: row[3],
+            mean: row[4],
+            deviation: row[5],
+            min_x: row[6] as i32,
+            min_y: row[7] as i32,
+            max_x: row[8] as i32,
+            max_y: row[9] as i32,
+        }
+    }
+}
+
+/// The result of `stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageStats {
+    /// All bands taken together. The positions are those of a band which holds the extreme.
+    pub overall: BandStats,
+    pub bands: Vec<BandStats>,
+}
+
+impl VipsImage {
+    /// Computes min, max, sum, mean and deviation of every band and of the whole image
+    pub fn statistics(&self) -> Result<ImageStats> {
+        // one row for all bands, then one row per band, of ten columns
+        let matrix = self
+            .stats()?
+            .cast(BandFormat::Double)?;
+        let columns = matrix.get_width() as usize;
+        let rows = matrix.get_height() as usize;
+        if columns < 10 || rows < 2 {
+            return Err(Error::OperationError("Unexpected stats matrix"));
+        }
+        let values = doubles(&matrix);
+        let mut rows = values
+            .chunks_exact(columns)
+            .map(BandStats::from_row);
+        Ok(
+            ImageStats {
+                overall: rows
+                    .next()
+                    .unwrap(),
+                bands: rows.collect(),
+            },
+        )
+    }
+}
+
+/// Bin counts of every band, as made by `hist_find`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bands: Vec<Vec<u64>>,
+}
+
+impl Histogram {
+    /// Builds a histogram from bin counts, one `Vec` per band, all of the same length
+    pub fn from_counts(bands: Vec<Vec<u64>>) -> Result<Histogram> {
+        let bins = bands
+            .first()
+            .map_or(
+                0,
+                |band| band.len(),
+            );
+        if bins == 0
+            || bands
+                .iter()
+                .any(|band| band.len() != bins)
+        {
+            return Err(
+                Error::OperationError(
+                    "A histogram needs bands of the same, non-zero number of bins",
+                ),
+            );
+        }
+        Ok(
+            Histogram {
+                bands,
+            },
+        )
+    }
+
+    /// Reads a histogram image, e.g. from `hist_find` or `hist_find_ndim`, with one bin per pixel
+    pub fn from_image(image: &VipsImage) -> Result<Histogram> {
+        let n_bands = image.get_bands() as usize;
+        let values = doubles(&image.cast(BandFormat::Double)?);
+        let mut bands = vec![Vec::with_capacity(values.len() / n_bands); n_bands];
+        for pixel in values.chunks_exact(n_bands) {
+            for (band, value) in bands
+                .iter_mut()
+                .zip(pixel)
+            {
+                band.push(value.max(0.0) as u64);
+            }
+        }
+        Histogram::from_counts(bands)
+    }
+
+    pub fn bands(&self) -> &[Vec<u64>] {
+        &self.bands
+    }
+
+    pub fn band(&self, band: usize) -> &[u64] {
+        &self.bands[band]
+    }
+
+    pub fn bins(&self) -> usize {
+        self.bands[0].len()
+    }
+
+    /// The number of pixels counted in a band
+    pub fn total(&self, band: usize) -> u64 {
+        self.bands[band]
+            .iter()
+            .sum()
+    }
+
+    /// The first bin at which the cumulative count reaches `percent` of the total, e.g. 50 for the median
+    pub fn percentile(&self, band: usize, percent: f64) -> usize {
+        let target = self.total(band) as f64 * percent.clamp(0.0, 100.0) / 100.0;
+        let mut sum = 0;
+        for (bin, count) in self.bands[band]
+            .iter()
+            .enumerate()
+        {
+            sum += count;
+            if sum as f64 >= target && sum > 0 {
+                return bin;
+            }
+        }
+        self.bins() - 1
+    }
+
+    /// The most common bin, the first one on a tie
+    pub fn mode(&self, band: usize) -> usize {
+        let counts = &self.bands[band];
+        let max = counts
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or(0);
+        counts
+            .iter()
+            .position(|&count| count == max)
+            .unwrap_or(0)
+    }
+
+    /// Shannon entropy of a band in bits, 0 for a flat image and 8 for evenly spread 8-bit values
+    pub fn entropy(&self, band: usize) -> f64 {
+        let total = self.total(band) as f64;
+        if total == 0.0 {
+            return 0.0;
+        }
+        self.bands[band]
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Running totals, like `hist_cum`
+    pub fn cumulative(&self) -> Histogram {
+        Histogram {
+            bands: self
+                .bands
+                .iter()
+                .map(|band| {
+                    band.iter()
+                        .scan(
+                            0,
+                            |sum, count| {
+                                *sum += count;
+                                Some(*sum)
+                            },
+                        )
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// The fraction of pixels in each bin of each band, each band summing to 1
+    pub fn normalized(&self) -> Vec<Vec<f64>> {
+        (0..self
+            .bands
+            .len())
+            .map(|band| {
+                let total = self
+                    .total(band)
+                    .max(1) as f64;
+                self.bands[band]
+                    .iter()
+                    .map(|&count| count as f64 / total)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes the counts back to a one row histogram image, for `hist_plot` and friends
+    pub fn to_image(&self) -> Result<VipsImage> {
+        let values: Vec<Vec<f64>> = self
+            .bands
+            .iter()
+            .map(|band| {
+                band.iter()
+                    .map(|&count| count as f64)
+                    .collect()
+            })
+            .collect();
+        join_rows(&values)?.cast(BandFormat::Uint)
+    }
+
+    /// Builds a lookup table for `maplut` with one entry per bin and band, computed by
+    /// `f(band, bin)`. Values are cast to `format`.
+    pub fn lut<F>(&self, format: BandFormat, f: F) -> Result<VipsImage>
+    where
+        F: Fn(usize, usize) -> f64,
+    {
+        let values: Vec<Vec<f64>> = (0..self
+            .bands
+            .len())
+            .map(|band| {
+                (0..self.bins())
+                    .map(|bin| f(band, bin))
+                    .collect()
+            })
+            .collect();
+        join_rows(&values)?.cast(format)
+    }
+
+    /// A lookup table which stretches the range between two percentiles of each band to the full
+    /// range of bins, e.g. `stretch(1.0, 99.0)` for auto-levels of an 8-bit image
+    pub fn stretch(&self, low_percent: f64, high_percent: f64) -> Result<VipsImage> {
+        let top = (self.bins() - 1) as f64;
+        let ranges: Vec<(f64, f64)> = (0..self
+            .bands
+            .len())
+            .map(|band| {
+                (
+                    self.percentile(
+                        band,
+                        low_percent,
+                    ) as f64,
+                    self.percentile(
+                        band,
+                        high_percent,
+                    ) as f64,
+                )
+            })
+            .collect();
+        let format = if self.bins() <= 256 { BandFormat::Uchar } else { BandFormat::Ushort };
+        self.lut(
+            format,
+            |band, bin| {
+                let (low, high) = ranges[band];
+                if high <= low {
+                    bin as f64
+                } else {
+                    ((bin as f64 - low) / (high - low) * top).clamp(0.0, top)
+                }
+            },
+        )
+    }
+}
+
+impl VipsImage {
+    /// Counts the values of every band, see `hist_find`
+    pub fn histogram(&self) -> Result<Histogram> {
+        Histogram::from_image(&self.hist_find()?)
+    }
+}
+
+/// One row per band, joined into a single row image of that many bands
+fn join_rows(values: &[Vec<f64>]) -> Result<VipsImage> {
+    let rows = values
+        .iter()
+        .map(|row| {
+            VipsImage::new_matrix_from_array(
+                row.len() as i32,
+                1,
+                row,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if rows.len() == 1 {
+        return rows
+            .into_iter()
+            .next()
+            .unwrap()
+            .copy();
+    }
+    VipsImage::bandjoin(&rows)
+}
+
+/// The pixels of a double image, bands interleaved
+fn doubles(image: &VipsImage) -> Vec<f64> {
+    image
+        .image_write_to_memory()
+        .chunks_exact(8)
+        .map(|b| f64::from_ne_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .collect()
+}
@@ -0,0 +1,136 @@
+//! Golden image testing
+//!
+//! [`assert_image_eq!`] compares an image with a reference file. When they differ, a diff image
+//! is written next to the reference, e.g. `thumbnail.diff.png` for `thumbnail.png`, with the
+//! absolute difference amplified so small errors are visible.
+use crate::{
+    compare::Metrics,
+    ops::{BandFormat, Interpretation},
+    v_value,
+    voption::VOption,
+    VipsImage,
+};
+use std::path::Path;
+
+/// How much the absolute difference is amplified in the diff image
+const DIFF_GAIN: f64 = 10.0;
+
+/// Asserts that an image matches a reference file, to within `tolerance` of any pixel value
+#[macro_export]
+macro_rules! assert_image_eq {
+    ($actual:expr, $expected_path:expr, $tolerance:expr) => {
+        if let Err(message) = $crate::testing::check_image_eq(
+            &$actual,
+            $expected_path,
+            $tolerance,
+        ) {
+            panic!(
+                "{}",
+                message
+            );
+        }
+    };
+}
+
+/// Compares an image with a reference file. The error describes the first difference found.
+pub fn check_image_eq(
+    actual: &VipsImage,
+    expected_path: impl AsRef<Path>,
+    tolerance: f64,
+) -> std::result::Result<(), String> {
+    let path = expected_path.as_ref();
+    let expected = VipsImage::new_from_file(&path.to_string_lossy()).map_err(|e| {
+        format!(
+            "Cannot load reference {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    let actual_size = (
+        actual.get_width(),
+        actual.get_height(),
+        actual.get_bands(),
+    );
+    let expected_size = (
+        expected.get_width(),
+        expected.get_height(),
+        expected.get_bands(),
+    );
+    if actual_size != expected_size {
+        return Err(format!(
+            "Image is {}x{} with {} bands, but reference {} is {}x{} with {} bands",
+            actual_size.0,
+            actual_size.1,
+            actual_size.2,
+            path.display(),
+            expected_size.0,
+            expected_size.1,
+            expected_size.2
+        ));
+    }
+    let actual_format = actual
+        .get_format()
+        .map_err(|e| e.to_string())?;
+    let expected_format = expected
+        .get_format()
+        .map_err(|e| e.to_string())?;
+    if actual_format != expected_format {
+        return Err(format!(
+            "Image format is {:?}, but reference {} is {:?}",
+            actual_format,
+            path.display(),
+            expected_format
+        ));
+    }
+
+    let difference = actual
+        .cast(BandFormat::Double)
+        .and_then(|a| a.subtract(&expected.cast(BandFormat::Double)?))
+        .and_then(|d| d.abs())
+        .map_err(|e| e.to_string())?;
+    let max_difference = difference
+        .max()
+        .map_err(|e| e.to_string())?;
+    if max_difference <= tolerance {
+        return Ok(());
+    }
+
+    let max_de00 = actual
+        .compare(&expected)
+        .ok()
+        .and_then(|metrics: Metrics| metrics.max_de00)
+        .unwrap_or(f64::NAN);
+    let diff_path = path.with_extension("diff.png");
+    let written = difference
+        .linear(
+            &[DIFF_GAIN],
+            &[0.0],
+        )
+        .and_then(|d| d.cast(BandFormat::Uchar))
+        .and_then(|d| {
+            // keep the pixel values as they are if the source was Lab or another space
+            let d = if d.get_bands() >= 3 {
+                d.copy_with_opts(
+                    VOption::new().set(
+                        "interpretation",
+                        v_value!(Interpretation::Srgb as i32),
+                    ),
+                )?
+            } else {
+                d
+            };
+            d.pngsave(&diff_path.to_string_lossy())
+        });
+    Err(format!(
+        "Image differs from reference {}: max absolute difference {} exceeds tolerance {}, max dE00 {:.2}; {}",
+        path.display(),
+        max_difference,
+        tolerance,
+        max_de00,
+        match written {
+            Ok(()) => format!("diff written to {}", diff_path.display()),
+            Err(e) => format!("could not write diff image: {}", e),
+        }
+    ))
+}
@@ -0,0 +1,389 @@
+//! Text rendering
+//!
+//! [`TextBuilder`] collects the arguments of `text`, renders Pango markup to an sRGB image with
+//! alpha, and reports the size and autofit DPI of the result. `VipsImage::add_text` composites
+//! the text onto an image at a compass position, optionally over a blurred drop shadow:
+//!
+//! ```no_run
+//! use libvips::{ops::CompassDirection, text::{Shadow, TextBuilder}, VipsImage};
+//!
+//! let photo = VipsImage::new_from_file("photo.jpg").unwrap();
+//! let caption = TextBuilder::new("<b>Hello</b>")
+//!     .font("sans 48")
+//!     .colour(&[255.0, 255.0, 255.0])
+//!     .shadow(Shadow::default());
+//! let captioned = photo.add_text(&caption, CompassDirection::South, 20).unwrap();
+//! ```
+use crate::{
+    ops::{Align, BandFormat, BlendMode, CompassDirection, Extend, Interpretation, TextWrap},
+    overlay::{composite_at, position},
+    v_value,
+    voption::VOption,
+    Result, VipsImage,
+};
+
+/// A blurred copy of the text drawn beneath it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shadow {
+    /// Offset from the text, in pixels
+    pub x: i32,
+    pub y: i32,
+    /// Blur radius, as the Gaussian sigma
+    pub sigma: f64,
+    /// RGB
+    pub colour: Vec<f64>,
+    /// 0 to 1
+    pub opacity: f64,
+}
+
+impl Default for Shadow {
+    /// A soft black shadow below and right of the text
+    fn default() -> Shadow {
+        Shadow {
+            x: 2,
+            y: 2,
+            sigma: 2.0,
+            colour: vec![0.0, 0.0, 0.0],
+            opacity: 0.6,
+        }
+    }
+}
+
+/// The size of rendered text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextMetrics {
+    pub width: i32,
+    pub height: i32,
+    /// The DPI chosen to fill the box when both width and height are set, otherwise the DPI used
+    pub dpi: i32,
+}
+
+/// The arguments of `text`, see the module docs
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextBuilder {
+    markup: String,
+    font: Option<String>,
+    fontfile: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    align: Align,
+    justify: bool,
+    dpi: Option<i32>,
+    spacing: Option<i32>,
+    wrap: Option<TextWrap>,
+    colour: Option<Vec<f64>>,
+    shadow: Option<Shadow>,
+}
+
+impl TextBuilder {
+    /// Text with Pango markup, e.g. `<span foreground="red">warning</span>`
+    pub fn new(markup: &str) -> TextBuilder {
+        TextBuilder {
+            markup: markup.to_string(),
+            font: None,
+            fontfile: None,
+            width: None,
+            height: None,
+            align: Align::Low,
+            justify: false,
+            dpi: None,
+            spacing: None,
+            wrap: None,
+            colour: None,
+            shadow: None,
+        }
+    }
+
+    /// A Pango font description, e.g. `sans bold 12`
+    pub fn font(mut self, font: &str) -> TextBuilder {
+        self.font = Some(font.to_string());
+        self
+    }
+
+    /// A font file to load, so `font` can name a family which is not installed
+    pub fn fontfile(mut self, fontfile: &str) -> TextBuilder {
+        self.fontfile = Some(fontfile.to_string());
+        self
+    }
+
+    /// Wraps lines at this width in pixels
+    pub fn width(mut self, width: i32) -> TextBuilder {
+        self.width = Some(width);
+        self
+    }
+
+    /// With `width`, picks the DPI which fills the box
+    pub fn height(mut self, height: i32) -> TextBuilder {
+        self.height = Some(height);
+        self
+    }
+
+    pub fn align(mut self, align: Align) -> TextBuilder {
+        self.align = align;
+        self
+    }
+
+    pub fn justify(mut self, justify: bool) -> TextBuilder {
+        self.justify = justify;
+        self
+    }
+
+    /// The resolution to render at, 72 by default
+    pub fn dpi(mut self, dpi: i32) -> TextBuilder {
+        self.dpi = Some(dpi);
+        self
+    }
+
+    /// Line spacing in points
+    pub fn spacing(mut self, spacing: i32) -> TextBuilder {
+        self.spacing = Some(spacing);
+        self
+    }
+
+    pub fn wrap(mut self, wrap: TextWrap) -> TextBuilder {
+        self.wrap = Some(wrap);
+        self
+    }
+
+    /// Paints all the text in one RGB colour. Without it, colours come from the markup and
+    /// default to black.
+    pub fn colour(mut self, colour: &[f64]) -> TextBuilder {
+        self.colour = Some(colour.to_vec());
+        self
+    }
+
+    /// Draws a shadow beneath the text when it is added to an image
+    pub fn shadow(mut self, shadow: Shadow) -> TextBuilder {
+        self.shadow = Some(shadow);
+        self
+    }
+
+    /// Renders the text to find its size, without building the coloured image
+    pub fn measure(&self) -> Result<TextMetrics> {
+        let (mask, dpi) = self.render_text(false)?;
+        Ok(
+            TextMetrics {
+                width: mask.get_width(),
+                height: mask.get_height(),
+                dpi,
+            },
+        )
+    }
+
+    /// Renders the text to an sRGB image with alpha
+    pub fn render(&self) -> Result<VipsImage> {
+        match &self.colour {
+            Some(colour) => {
+                let (mask, _) = self.render_text(false)?;
+                let ink = VipsImage::new_from_image(
+                    &mask,
+                    colour,
+                )?;
+                srgb(&VipsImage::bandjoin(&[ink, mask])?)
+            }
+            None => Ok(self
+                .render_text(true)?
+                .0),
+        }
+    }
+
+    /// Runs `text`, as a one band mask or as RGBA, and returns the DPI it used
+    fn render_text(
+        &self,
+        rgba: bool,
+    ) -> Result<(
+        VipsImage,
+        i32,
+    )> {
+        let mut autofit_dpi = 0;
+        let mut option = VOption::new()
+            .set(
+                "align",
+                v_value!(self.align as i32),
+            )
+            .set(
+                "justify",
+                v_value!(self.justify),
+            )
+            .set(
+                "rgba",
+                v_value!(rgba),
+            );
+        if let Some(font) = &self.font {
+            option.add(
+                "font",
+                v_value!(font.as_str()),
+            );
+        }
+        if let Some(fontfile) = &self.fontfile {
+            option.add(
+                "fontfile",
+                v_value!(fontfile.as_str()),
+            );
+        }
+        if let Some(width) = self.width {
+            option.add(
+                "width",
+                v_value!(width),
+            );
+        }
+        if let Some(height) = self.height {
+            option.add(
+                "height",
+                v_value!(height),
+            );
+        }
+        if let Some(dpi) = self.dpi {
+            option.add(
+                "dpi",
+                v_value!(dpi),
+            );
+        }
+        if let Some(spacing) = self.spacing {
+            option.add(
+                "spacing",
+                v_value!(spacing),
+            );
+        }
+        if let Some(wrap) = self.wrap {
+            option.add(
+                "wrap",
+                v_value!(wrap as i32),
+            );
+        }
+        let autofit = self
+            .width
+            .is_some()
+            && self
+                .height
+                .is_some();
+        if autofit {
+            option.add(
+                "autofit_dpi",
+                v_value!(&mut autofit_dpi),
+            );
+        }
+        let image = VipsImage::text_with_opts(
+            &self.markup,
+            option,
+        )?;
+        let dpi = if autofit {
+            autofit_dpi
+        } else {
+            self.dpi
+                .unwrap_or(72)
+        };
+        Ok((image, dpi))
+    }
+}
+
+impl VipsImage {
+    /// Composites rendered text at a compass position, `margin` pixels in from the edges
+    pub fn add_text(
+        &self,
+        text: &TextBuilder,
+        gravity: CompassDirection,
+        margin: i32,
+    ) -> Result<VipsImage> {
+        let rendered = text.render()?;
+        let (x, y) = position(
+            gravity,
+            (
+                self.get_width(),
+                self.get_height(),
+            ),
+            (
+                rendered.get_width(),
+                rendered.get_height(),
+            ),
+            margin,
+        );
+        let (layer, x, y) = match &text.shadow {
+            Some(shadow) => {
+                let pad = (3.0 * shadow.sigma).ceil() as i32
+                    + shadow
+                        .x
+                        .abs()
+                        .max(
+                            shadow
+                                .y
+                                .abs(),
+                        );
+                (
+                    with_shadow(
+                        &rendered,
+                        shadow,
+                        pad,
+                    )?,
+                    x - pad,
+                    y - pad,
+                )
+            }
+            None => (
+                rendered,
+                x,
+                y,
+            ),
+        };
+        composite_at(
+            self,
+            &layer,
+            x,
+            y,
+            BlendMode::Over,
+        )
+    }
+}
+
+/// Marks an image as sRGB
+fn srgb(image: &VipsImage) -> Result<VipsImage> {
+    image.copy_with_opts(
+        VOption::new().set(
+            "interpretation",
+            v_value!(Interpretation::Srgb as i32),
+        ),
+    )
+}
+
+/// The text over its shadow, padded by `pad` on every side so the blur is not cut off
+fn with_shadow(text: &VipsImage, shadow: &Shadow, pad: i32) -> Result<VipsImage> {
+    let width = text.get_width() + 2 * pad;
+    let height = text.get_height() + 2 * pad;
+    let embed = |image: &VipsImage, x: i32, y: i32| {
+        image.embed_with_opts(
+            x,
+            y,
+            width,
+            height,
+            VOption::new().set(
+                "extend",
+                v_value!(Extend::Black as i32),
+            ),
+        )
+    };
+    let alpha = embed(
+        &text.extract_band(3)?,
+        pad + shadow.x,
+        pad + shadow.y,
+    )?
+    .gaussblur(shadow.sigma)?
+    .linear(
+        &[shadow
+            .opacity
+            .clamp(0.0, 1.0)],
+        &[0.0],
+    )?
+    .cast(BandFormat::Uchar)?;
+    let ink = VipsImage::new_from_image(
+        &alpha,
+        &shadow.colour,
+    )?;
+    let shadow = srgb(&VipsImage::bandjoin(&[ink, alpha])?)?;
+    shadow.composite2_with_opts(
+        &embed(
+            text, pad, pad,
+        )?,
+        BlendMode::Over,
+        VOption::new(),
+    )
+}
@@ -4,7 +4,8 @@ use crate::error::Error;
 use crate::Result;
 use crate::VipsImage;
 use std::ffi::c_void;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
 
 pub(crate) struct VipsArrayIntWrapper {
     pub ctx: *mut VipsArrayInt,
@@ -102,6 +103,19 @@ pub(crate) fn new_c_string(string: &str) -> Result<CString> {
     CString::new(string).map_err(|_| Error::InitializationError("Error initializing C string."))
 }
 
+/// Copies a C string owned by libvips, an empty string for null
+pub(crate) fn c_string_lossy(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe {
+            CStr::from_ptr(ptr)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
 #[inline]
 pub(crate) fn ensure_null_terminated(input: impl AsRef<[u8]>) -> Vec<u8> {
     let bytes = input.as_ref();
@@ -0,0 +1,1511 @@
+//! Tests of the operation wrappers on synthetic images, so no fixtures are needed.
+//! Run with `cargo test --features testing`.
+use libvips::{
+    assert_image_eq,
+    canvas::{Canvas, Shape},
+    encode::{Encoder, OutputFormat, Quality},
+    format::ImageFormat,
+    hash::hamming_distance,
+    iiif::{IiifRequest, Region, Size},
+    matrix::Matrix,
+    ops::{Angle, BandFormat, BlendMode, CompassDirection, Direction, Interesting, Interpretation},
+    overlay::Placement,
+    pipeline::{Pipeline, PipelineOutput, Step},
+    pyramid::{Layout, Tile, TilePyramid},
+    resize::{Fit, Position, Resize},
+    text::{Shadow, TextBuilder},
+    v_value,
+    voption::VOption,
+    Vips, VipsImage,
+};
+use std::path::PathBuf;
+use std::sync::Once;
+
+static INIT: Once = Once::new();
+
+fn init() {
+    INIT.call_once(|| {
+        Vips::init(
+            "libvips-tests",
+            false,
+        )
+        .expect("Cannot initialise libvips")
+    });
+}
+
+/// Scales a synthetic image from `low..high` to 0..255 and casts it to uchar
+fn to_uchar(image: VipsImage, low: f64, high: f64) -> VipsImage {
+    let scale = 255.0 / (high - low);
+    image
+        .linear(
+            &[scale],
+            &[-low * scale],
+        )
+        .unwrap()
+        .cast(BandFormat::Uchar)
+        .unwrap()
+}
+
+/// A 64x48 sRGB image with a zone plate, sine waves and a horizontal ramp in its three bands
+fn rgb_image() -> VipsImage {
+    init();
+    let zone = to_uchar(
+        VipsImage::zone(64, 48).unwrap(),
+        -1.0,
+        1.0,
+    );
+    let sines = to_uchar(
+        VipsImage::sines(64, 48).unwrap(),
+        -1.0,
+        1.0,
+    );
+    let ramp = to_uchar(
+        VipsImage::xyz(64, 48)
+            .unwrap()
+            .extract_band(0)
+            .unwrap(),
+        0.0,
+        63.0,
+    );
+    VipsImage::bandjoin(&[zone, sines, ramp])
+        .unwrap()
+        .copy_with_opts(
+            VOption::new().set(
+                "interpretation",
+                v_value!(Interpretation::Srgb as i32),
+            ),
+        )
+        .unwrap()
+}
+
+/// A path in a fresh temporary directory for reference files
+fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "libvips-tests-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn save_reference(image: &VipsImage, name: &str) -> PathBuf {
+    let path = temp_path(name);
+    image
+        .image_write_to_file(&path.to_string_lossy())
+        .unwrap();
+    path
+}
+
+#[test]
+fn black_is_zero() {
+    init();
+    let image = VipsImage::black(32, 16).unwrap();
+    assert_eq!(
+        image.get_width(),
+        32
+    );
+    assert_eq!(
+        image.get_height(),
+        16
+    );
+    assert_eq!(
+        image.get_bands(),
+        1
+    );
+    assert_eq!(
+        image
+            .avg()
+            .unwrap(),
+        0.0
+    );
+    assert_eq!(
+        image
+            .max()
+            .unwrap(),
+        0.0
+    );
+}
+
+#[test]
+fn xyz_holds_coordinates() {
+    init();
+    let image = VipsImage::xyz(10, 8).unwrap();
+    assert_eq!(
+        image.get_bands(),
+        2
+    );
+    assert_eq!(
+        image
+            .getpoint(3, 5)
+            .unwrap(),
+        vec![3.0, 5.0]
+    );
+}
+
+#[test]
+fn invert_black_is_white() {
+    init();
+    let image = VipsImage::black(4, 4)
+        .unwrap()
+        .cast(BandFormat::Uchar)
+        .unwrap()
+        .invert()
+        .unwrap();
+    assert_eq!(
+        image
+            .min()
+            .unwrap(),
+        255.0
+    );
+}
+
+#[test]
+fn embed_then_extract_is_identity() {
+    let image = rgb_image();
+    let reference = save_reference(
+        &image,
+        "embed.png",
+    );
+    let embedded = image
+        .embed(
+            10, 20, 100, 100,
+        )
+        .unwrap();
+    assert_eq!(
+        embedded.get_width(),
+        100
+    );
+    let extracted = embedded
+        .extract_area(
+            10, 20, 64, 48,
+        )
+        .unwrap();
+    assert_image_eq!(
+        extracted,
+        &reference,
+        0.0
+    );
+}
+
+#[test]
+fn flip_twice_is_identity() {
+    let image = rgb_image();
+    let reference = save_reference(
+        &image,
+        "flip.png",
+    );
+    for direction in [Direction::Horizontal, Direction::Vertical] {
+        let flipped = image
+            .flip(direction)
+            .unwrap()
+            .flip(direction)
+            .unwrap();
+        assert_image_eq!(
+            flipped,
+            &reference,
+            0.0
+        );
+    }
+}
+
+#[test]
+fn rotate_swaps_dimensions() {
+    let image = rgb_image();
+    let rotated = image
+        .rot(Angle::D90)
+        .unwrap();
+    assert_eq!(
+        rotated.get_width(),
+        48
+    );
+    assert_eq!(
+        rotated.get_height(),
+        64
+    );
+    let reference = save_reference(
+        &image,
+        "rot.png",
+    );
+    let back = rotated
+        .rot(Angle::D270)
+        .unwrap();
+    assert_image_eq!(
+        back,
+        &reference,
+        0.0
+    );
+}
+
+#[test]
+fn resize_scales_dimensions() {
+    let image = rgb_image();
+    let resized = image
+        .resize(0.5)
+        .unwrap();
+    assert_eq!(
+        resized.get_width(),
+        32
+    );
+    assert_eq!(
+        resized.get_height(),
+        24
+    );
+    assert_eq!(
+        resized.get_bands(),
+        3
+    );
+}
+
+#[test]
+fn lab_round_trip_is_close() {
+    let image = rgb_image();
+    let reference = save_reference(
+        &image,
+        "lab.png",
+    );
+    let round_trip = image
+        .colourspace(Interpretation::Lab)
+        .unwrap()
+        .colourspace(Interpretation::Srgb)
+        .unwrap();
+    assert_image_eq!(
+        round_trip,
+        &reference,
+        1.0
+    );
+}
+
+#[test]
+#[should_panic(expected = "diff written to")]
+fn mismatch_writes_diff() {
+    let image = rgb_image();
+    let reference = save_reference(
+        &image,
+        "mismatch.png",
+    );
+    let brighter = image
+        .linear(
+            &[1.0],
+            &[20.0],
+        )
+        .unwrap()
+        .cast(BandFormat::Uchar)
+        .unwrap();
+    assert_image_eq!(
+        brighter,
+        &reference,
+        1.0
+    );
+}
+
+#[test]
+fn compare_identical_images() {
+    let image = rgb_image();
+    let metrics = image
+        .compare(&rgb_image())
+        .unwrap();
+    assert_eq!(
+        metrics.mse,
+        0.0
+    );
+    assert!(metrics
+        .psnr
+        .is_infinite());
+    assert!((metrics.ssim - 1.0).abs() < 1e-6);
+    assert!(
+        metrics
+            .max_de00
+            .unwrap()
+            < 1e-6
+    );
+}
+
+#[test]
+fn compare_without_a_route_to_lab_keeps_the_other_metrics() {
+    let cmyk = rgb_image()
+        .bandjoin_const(&[0.0])
+        .unwrap()
+        .copy_with_opts(
+            VOption::new().set(
+                "interpretation",
+                v_value!(Interpretation::Cmyk as i32),
+            ),
+        )
+        .unwrap();
+    let metrics = cmyk
+        .compare(&cmyk)
+        .unwrap();
+    assert_eq!(
+        metrics.mse,
+        0.0
+    );
+    assert!((metrics.ssim - 1.0).abs() < 1e-6);
+    assert!(metrics
+        .max_de00
+        .is_none_or(|de| de < 1e-6));
+}
+
+#[test]
+fn jpeg_round_trip_keeps_quality() {
+    let image = rgb_image();
+    let bytes = image
+        .save_buffer(
+            &Encoder::new(
+                OutputFormat::Jpeg,
+                Quality::Value(90),
+            ),
+        )
+        .unwrap();
+    let detected = VipsImage::detect_format(&bytes).unwrap();
+    assert_eq!(
+        detected.format,
+        ImageFormat::Jpeg
+    );
+    let decoded = VipsImage::new_from_buffer(&bytes, "").unwrap();
+    let metrics = image
+        .compare(&decoded)
+        .unwrap();
+    assert!(
+        metrics.psnr > 25.0,
+        "psnr {}",
+        metrics.psnr
+    );
+}
+
+#[test]
+fn frames_round_trip() {
+    let image = rgb_image();
+    let frames = vec![
+        rgb_image(),
+        image
+            .invert()
+            .unwrap(),
+    ];
+    let animation = VipsImage::from_frames(
+        &frames,
+        &[100, 200],
+        3,
+    )
+    .unwrap();
+    assert_eq!(
+        animation.get_height(),
+        96
+    );
+    assert_eq!(
+        animation.frame_count(),
+        2
+    );
+    assert_eq!(
+        animation.frame_delays(),
+        vec![100, 200]
+    );
+    assert_eq!(
+        animation.loop_count(),
+        3
+    );
+    let split = animation
+        .frames()
+        .unwrap();
+    assert_eq!(
+        split.len(),
+        2
+    );
+    assert_eq!(
+        split[1].delay,
+        200
+    );
+    assert_eq!(
+        split[0]
+            .image
+            .compare(&image)
+            .unwrap()
+            .mse,
+        0.0
+    );
+}
+
+#[test]
+fn hashes_survive_resizing() {
+    let image = rgb_image();
+    let resized = image
+        .resize(0.75)
+        .unwrap();
+    let inverted = image
+        .invert()
+        .unwrap();
+    assert!(
+        hamming_distance(
+            image
+                .phash()
+                .unwrap(),
+            resized
+                .phash()
+                .unwrap()
+        ) <= 10
+    );
+    assert!(
+        hamming_distance(
+            image
+                .dhash()
+                .unwrap(),
+            resized
+                .dhash()
+                .unwrap()
+        ) <= 10
+    );
+    assert!(
+        hamming_distance(
+            image
+                .ahash()
+                .unwrap(),
+            inverted
+                .ahash()
+                .unwrap()
+        ) > 32
+    );
+}
+
+#[test]
+fn canvas_leaves_source_unchanged() {
+    let image = rgb_image();
+    let reference = save_reference(
+        &image,
+        "canvas.png",
+    );
+    let mut canvas = Canvas::new(&image).unwrap();
+    canvas
+        .draw(&[
+            Shape::Rect {
+                ink: vec![255.0, 0.0, 0.0],
+                left: 0,
+                top: 0,
+                width: 10,
+                height: 10,
+                fill: true,
+            },
+            Shape::Line {
+                ink: vec![0.0, 255.0, 0.0],
+                x1: 0,
+                y1: 47,
+                x2: 63,
+                y2: 0,
+            },
+        ])
+        .unwrap();
+    let drawn = canvas.finish();
+    assert_eq!(
+        drawn
+            .getpoint(5, 5)
+            .unwrap(),
+        vec![255.0, 0.0, 0.0]
+    );
+    assert_image_eq!(
+        image,
+        &reference,
+        0.0
+    );
+}
+
+#[test]
+fn statistics_of_constant_bands() {
+    init();
+    let image = VipsImage::black_with_opts(
+        8,
+        4,
+        VOption::new().set(
+            "bands",
+            v_value!(2),
+        ),
+    )
+    .unwrap()
+    .linear(
+        &[1.0, 1.0],
+        &[10.0, 30.0],
+    )
+    .unwrap();
+    let stats = image
+        .statistics()
+        .unwrap();
+    assert_eq!(
+        stats
+            .bands
+            .len(),
+        2
+    );
+    assert_eq!(
+        stats.bands[0].mean,
+        10.0
+    );
+    assert_eq!(
+        stats.bands[1].max,
+        30.0
+    );
+    assert_eq!(
+        stats.bands[1].deviation,
+        0.0
+    );
+    assert_eq!(
+        stats
+            .overall
+            .min,
+        10.0
+    );
+    assert_eq!(
+        stats
+            .overall
+            .max,
+        30.0
+    );
+    assert_eq!(
+        stats
+            .overall
+            .mean,
+        20.0
+    );
+}
+
+#[test]
+fn histogram_of_ramp() {
+    init();
+    // every value from 0 to 255 once per row
+    let ramp = VipsImage::xyz(256, 4)
+        .unwrap()
+        .extract_band(0)
+        .unwrap()
+        .cast(BandFormat::Uchar)
+        .unwrap();
+    let histogram = ramp
+        .histogram()
+        .unwrap();
+    assert_eq!(
+        histogram.bins(),
+        256
+    );
+    assert_eq!(
+        histogram.total(0),
+        1024
+    );
+    assert_eq!(
+        histogram.percentile(0, 50.0),
+        127
+    );
+    assert!((histogram.entropy(0) - 8.0).abs() < 1e-9);
+    assert_eq!(
+        histogram
+            .cumulative()
+            .band(0)[255],
+        1024
+    );
+
+    let stretched = ramp
+        .linear(
+            &[0.5],
+            &[64.0],
+        )
+        .unwrap()
+        .cast(BandFormat::Uchar)
+        .unwrap();
+    let lut = stretched
+        .histogram()
+        .unwrap()
+        .stretch(0.0, 100.0)
+        .unwrap();
+    let levelled = stretched
+        .maplut(&lut)
+        .unwrap();
+    assert_eq!(
+        levelled
+            .min()
+            .unwrap(),
+        0.0
+    );
+    assert_eq!(
+        levelled
+            .max()
+            .unwrap(),
+        255.0
+    );
+}
+
+#[test]
+fn matrix_round_trips() {
+    init();
+    let sharpen: Matrix = "3 3 8 0\n-1 -1 -1\n-1 16 -1\n-1 -1 -1\n"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        sharpen.get(1, 1),
+        16.0
+    );
+    assert_eq!(
+        sharpen.scale,
+        8.0
+    );
+    assert_eq!(
+        sharpen
+            .to_string()
+            .parse::<Matrix>()
+            .unwrap(),
+        sharpen
+    );
+    assert_eq!(
+        Matrix::from_image(
+            &sharpen
+                .to_image()
+                .unwrap()
+        )
+        .unwrap(),
+        sharpen
+    );
+
+    let flat = VipsImage::black(16, 16)
+        .unwrap()
+        .linear(
+            &[1.0],
+            &[100.0],
+        )
+        .unwrap();
+    let blurred = flat
+        .conv_matrix(&Matrix::box_blur(3).unwrap())
+        .unwrap();
+    assert_eq!(
+        blurred
+            .getpoint(8, 8)
+            .unwrap(),
+        vec![100.0]
+    );
+}
+
+#[test]
+fn deep_zoom_tiles_cover_every_level() {
+    let image = rgb_image();
+    let pyramid = TilePyramid::new(Layout::DeepZoom)
+        .tile_size(16)
+        .overlap(1)
+        .encoder(Encoder::from(OutputFormat::Png));
+    let mut tiles = Vec::new();
+    pyramid
+        .build(
+            &image,
+            |tile| {
+                tiles.push(tile);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+    // 64x48 halves down to 1x1 in 7 levels
+    assert_eq!(
+        pyramid.level_count(64, 48),
+        7
+    );
+    assert_eq!(
+        tiles
+            .iter()
+            .filter(|tile| tile.level == 6)
+            .count(),
+        4 * 3
+    );
+    let corner = tiles
+        .iter()
+        .find(|tile| tile.level == 6 && tile.column == 1 && tile.row == 1)
+        .unwrap();
+    assert_eq!(
+        corner.path,
+        "image_files/6/1_1.png"
+    );
+    // an inner tile overlaps one pixel on each side
+    let decoded = VipsImage::new_from_buffer(
+        &corner.data,
+        "",
+    )
+    .unwrap();
+    assert_eq!(
+        (
+            decoded.get_width(),
+            decoded.get_height()
+        ),
+        (18, 18)
+    );
+    assert!(tiles
+        .iter()
+        .any(|tile| tile.level == 0 && tile.path == "image_files/0/0_0.png"));
+}
+
+/// Every tile of a pyramid of a 61x47 image, which has odd sizes at every level
+fn odd_pyramid(pyramid: &TilePyramid) -> Vec<Tile> {
+    let image = rgb_image()
+        .extract_area(
+            0, 0, 61, 47,
+        )
+        .unwrap();
+    let mut tiles = Vec::new();
+    pyramid
+        .build(
+            &image,
+            |tile| {
+                tiles.push(tile);
+                Ok(())
+            },
+        )
+        .unwrap();
+    tiles
+}
+
+fn tile_size(tile: &Tile) -> (i32, i32) {
+    let decoded = VipsImage::new_from_buffer(
+        &tile.data,
+        "",
+    )
+    .unwrap();
+    (
+        decoded.get_width(),
+        decoded.get_height(),
+    )
+}
+
+#[test]
+fn zoomify_tiles_are_grouped_from_the_smallest_level() {
+    let pyramid = TilePyramid::new(Layout::Zoomify)
+        .tile_size(16)
+        .encoder(Encoder::from(OutputFormat::Png));
+    let tiles = odd_pyramid(&pyramid);
+
+    // 61x47, 31x24 and 16x12, which fits in one tile
+    assert_eq!(
+        pyramid.level_count(61, 47),
+        3
+    );
+    assert_eq!(
+        tiles.len(),
+        12 + 4 + 1
+    );
+    // largest level first
+    assert_eq!(
+        tiles[0].path,
+        "TileGroup0/2-0-0.png"
+    );
+    let edge = tiles
+        .iter()
+        .find(|tile| tile.path == "TileGroup0/2-3-2.png")
+        .unwrap();
+    assert_eq!(
+        tile_size(edge),
+        (13, 15)
+    );
+    let smallest = tiles
+        .iter()
+        .find(|tile| tile.path == "TileGroup0/0-0-0.png")
+        .unwrap();
+    assert_eq!(
+        tile_size(smallest),
+        (16, 12)
+    );
+    let (name, properties) = pyramid
+        .descriptor(61, 47)
+        .unwrap();
+    assert_eq!(
+        name,
+        "ImageProperties.xml"
+    );
+    assert!(properties.contains("NUMTILES=\"17\""));
+
+    // with more than 256 tiles, the largest level spills into the next group
+    let pyramid = TilePyramid::new(Layout::Zoomify)
+        .tile_size(2)
+        .encoder(Encoder::from(OutputFormat::Png));
+    let tiles = odd_pyramid(&pyramid);
+    assert!(tiles
+        .iter()
+        .any(|tile| tile.path == "TileGroup0/0-0-0.png"));
+    assert!(tiles
+        .iter()
+        .any(|tile| tile.path == "TileGroup3/5-30-23.png"));
+}
+
+#[test]
+fn google_tiles_are_padded_to_full_size() {
+    let pyramid = TilePyramid::new(Layout::Google)
+        .tile_size(16)
+        .encoder(Encoder::from(OutputFormat::Png))
+        .background(&[0.0, 0.0, 0.0]);
+    let tiles = odd_pyramid(&pyramid);
+
+    assert_eq!(
+        tiles.len(),
+        12 + 4 + 1
+    );
+    assert!(pyramid
+        .descriptor(61, 47)
+        .is_none());
+    assert!(tiles
+        .iter()
+        .all(|tile| tile_size(tile) == (16, 16)));
+    // level, row, column
+    let edge = tiles
+        .iter()
+        .find(|tile| tile.level == 2 && tile.column == 3 && tile.row == 2)
+        .unwrap();
+    assert_eq!(
+        edge.path,
+        "2/2/3.png"
+    );
+    let decoded = VipsImage::new_from_buffer(
+        &edge.data,
+        "",
+    )
+    .unwrap();
+    // the padding is the background
+    assert_eq!(
+        decoded
+            .getpoint(15, 15)
+            .unwrap(),
+        vec![0.0, 0.0, 0.0]
+    );
+    assert!(tiles
+        .iter()
+        .any(|tile| tile.path == "0/0/0.png"));
+}
+
+#[test]
+fn iiif_tiles_are_named_by_full_size_region() {
+    let pyramid = TilePyramid::new(Layout::Iiif)
+        .tile_size(16)
+        .name("https://example.com/iiif/scan")
+        .encoder(Encoder::from(OutputFormat::Png));
+    let tiles = odd_pyramid(&pyramid);
+
+    assert_eq!(
+        tiles.len(),
+        12 + 4 + 1
+    );
+    let paths: Vec<&str> = tiles
+        .iter()
+        .map(|tile| {
+            tile.path
+                .as_str()
+        })
+        .collect();
+    // the full size edge tile
+    assert!(paths.contains(&"48,32,13,15/13,15/0/default.png"));
+    // half size: 32 full size pixels per 16 pixel tile, cut to the 31x24 level
+    assert!(paths.contains(&"32,0,29,32/15,16/0/default.png"));
+    // the whole image in one tile
+    assert!(paths.contains(&"0,0,61,47/16,12/0/default.png"));
+    for tile in &tiles {
+        let size: Vec<i32> = tile
+            .path
+            .split('/')
+            .nth(1)
+            .unwrap()
+            .split(',')
+            .map(|n| {
+                n.parse()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            tile_size(tile),
+            (
+                size[0],
+                size[1]
+            )
+        );
+    }
+    let (name, info) = pyramid
+        .descriptor(61, 47)
+        .unwrap();
+    assert_eq!(
+        name,
+        "info.json"
+    );
+    assert!(info.contains("\"id\": \"https://example.com/iiif/scan\""));
+    assert!(info.contains("\"scaleFactors\": [1, 2, 4]"));
+
+    // the id is escaped in the JSON
+    let (_, info) = TilePyramid::new(Layout::Iiif)
+        .name("a \"quoted\" C:\\path")
+        .descriptor(1, 1)
+        .unwrap();
+    assert!(info.contains(r#""id": "a \"quoted\" C:\\path","#));
+}
+
+#[test]
+fn iiif_request_renders_region_size_and_rotation() {
+    let image = rgb_image();
+    let request: IiifRequest = "iiif/3/scan/16,8,32,24/pct:50/!90/gray.png"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        request.region,
+        Region::Pixels {
+            x: 16,
+            y: 8,
+            width: 32,
+            height: 24
+        }
+    );
+    assert_eq!(
+        request.size,
+        Size::Percent(50.0)
+    );
+    assert!(request.mirror);
+
+    let (bytes, mime_type) = request
+        .render(&image)
+        .unwrap();
+    assert_eq!(
+        mime_type,
+        "image/png"
+    );
+    let decoded = VipsImage::new_from_buffer(&bytes, "").unwrap();
+    assert_eq!(
+        (
+            decoded.get_width(),
+            decoded.get_height(),
+            decoded.get_bands()
+        ),
+        (12, 16, 1)
+    );
+
+    // larger than the region without ^
+    let upscale: IiifRequest = "0,0,10,10/20,/0/default.jpg"
+        .parse()
+        .unwrap();
+    assert!(upscale
+        .render(&image)
+        .is_err());
+    assert!(
+        "full/max/0/sepia.jpg"
+            .parse::<IiifRequest>()
+            .is_err()
+    );
+    assert!(
+        "full/0,0/0/default.jpg"
+            .parse::<IiifRequest>()
+            .is_err()
+    );
+}
+
+#[test]
+fn text_is_measured_and_added_with_a_shadow() {
+    let image = rgb_image();
+    let text = TextBuilder::new("<b>Hi</b>")
+        .dpi(36)
+        .colour(&[255.0, 255.0, 0.0])
+        .shadow(Shadow::default());
+    let metrics = text
+        .measure()
+        .unwrap();
+    let rendered = text
+        .render()
+        .unwrap();
+    assert_eq!(
+        (
+            rendered.get_width(),
+            rendered.get_height(),
+            rendered.get_bands()
+        ),
+        (
+            metrics.width,
+            metrics.height,
+            4
+        )
+    );
+    assert_eq!(
+        metrics.dpi,
+        36
+    );
+
+    let captioned = image
+        .add_text(
+            &text,
+            CompassDirection::SouthEast,
+            2,
+        )
+        .unwrap();
+    assert_eq!(
+        (
+            captioned.get_width(),
+            captioned.get_height(),
+            captioned.get_bands()
+        ),
+        (64, 48, 3)
+    );
+}
+
+#[test]
+fn overlay_keeps_the_bands_of_the_base() {
+    let grey = rgb_image()
+        .colourspace(Interpretation::BW)
+        .unwrap();
+    let logo = VipsImage::black(8, 8)
+        .unwrap()
+        .linear(
+            &[1.0],
+            &[255.0],
+        )
+        .unwrap()
+        .cast(BandFormat::Uchar)
+        .unwrap();
+    let placement = Placement {
+        gravity: CompassDirection::NorthWest,
+        margin: 0,
+        ..Placement::default()
+    };
+    let marked = grey
+        .overlay(
+            &logo,
+            &placement,
+            BlendMode::Over,
+        )
+        .unwrap();
+    assert_eq!(
+        (
+            marked.get_width(),
+            marked.get_height(),
+            marked.get_bands()
+        ),
+        (64, 48, 1)
+    );
+    assert_eq!(
+        marked
+            .getpoint(4, 4)
+            .unwrap(),
+        vec![255.0]
+    );
+    assert_eq!(
+        marked
+            .getpoint(40, 40)
+            .unwrap(),
+        grey.getpoint(40, 40)
+            .unwrap()
+    );
+
+    let tiled = rgb_image()
+        .overlay(
+            &logo,
+            &Placement {
+                tile: true,
+                margin: 8,
+                opacity: 0.5,
+                ..Placement::default()
+            },
+            BlendMode::Over,
+        )
+        .unwrap();
+    assert_eq!(
+        tiled.get_bands(),
+        3
+    );
+}
+
+#[test]
+fn resize_fits_follow_sharp() {
+    let image = rgb_image();
+    let size = |resize: Resize| {
+        let out = resize
+            .apply(&image)
+            .unwrap();
+        (
+            out.get_width(),
+            out.get_height(),
+        )
+    };
+    let square = |fit: Fit| Resize {
+        fit,
+        ..Resize::new(
+            Some(32),
+            Some(32),
+        )
+    };
+
+    assert_eq!(
+        size(square(
+            Fit::Cover
+        )),
+        (32, 32)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Contain
+        )),
+        (32, 32)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Fill
+        )),
+        (32, 32)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Inside
+        )),
+        (32, 24)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Outside
+        )),
+        (43, 32)
+    );
+    assert_eq!(
+        size(
+            Resize::new(
+                Some(16),
+                None
+            )
+        ),
+        (16, 12)
+    );
+    assert_eq!(
+        size(Resize {
+            without_enlargement: true,
+            ..Resize::new(
+                Some(128),
+                Some(128)
+            )
+        }),
+        (64, 48)
+    );
+    assert_eq!(
+        size(Resize {
+            position: Position::Interesting(Interesting::Entropy),
+            ..square(Fit::Cover)
+        }),
+        (32, 32)
+    );
+
+    // contain pads above and below a 32x24 image
+    let padded = Resize {
+        background: vec![255.0, 255.0, 255.0],
+        ..square(Fit::Contain)
+    }
+    .apply(&image)
+    .unwrap();
+    assert_eq!(
+        padded
+            .getpoint(16, 0)
+            .unwrap(),
+        vec![255.0, 255.0, 255.0]
+    );
+    assert!(
+        Resize::new(None, None)
+            .apply(&image)
+            .is_err()
+    );
+}
+
+#[test]
+fn resize_follows_the_upright_aspect_ratio() {
+    // rgb_image is shared, so the orientation is set on a copy
+    let image = rgb_image()
+        .copy()
+        .unwrap();
+    image.set_int(
+        "orientation",
+        6,
+    );
+    let size = |resize: Resize| {
+        let out = resize
+            .apply(&image)
+            .unwrap();
+        (
+            out.get_width(),
+            out.get_height(),
+        )
+    };
+    let square = |fit: Fit| Resize {
+        fit,
+        background: vec![255.0, 255.0, 255.0],
+        ..Resize::new(
+            Some(32),
+            Some(32),
+        )
+    };
+
+    // upright, the image is 48x64
+    assert_eq!(
+        size(square(
+            Fit::Cover
+        )),
+        (32, 32)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Fill
+        )),
+        (32, 32)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Inside
+        )),
+        (24, 32)
+    );
+    assert_eq!(
+        size(square(
+            Fit::Outside
+        )),
+        (32, 43)
+    );
+    assert_eq!(
+        size(
+            Resize::new(
+                Some(24),
+                None
+            )
+        ),
+        (24, 32)
+    );
+    // contain pads left and right of a 24x32 image
+    let padded = square(Fit::Contain)
+        .apply(&image)
+        .unwrap();
+    assert_eq!(
+        (
+            padded.get_width(),
+            padded.get_height()
+        ),
+        (32, 32)
+    );
+    assert_eq!(
+        padded
+            .getpoint(1, 16)
+            .unwrap(),
+        vec![255.0, 255.0, 255.0]
+    );
+}
+
+#[test]
+fn auto_orient_rotates_and_resets_orientation() {
+    // rgb_image is shared, so the orientation is set on copies
+    let image = rgb_image()
+        .copy()
+        .unwrap();
+    image.set_int(
+        "orientation",
+        6,
+    );
+    let (upright, applied) = image
+        .auto_orient(false)
+        .unwrap();
+    assert_eq!(
+        (
+            upright.get_width(),
+            upright.get_height()
+        ),
+        (48, 64)
+    );
+    assert_eq!(
+        applied.angle,
+        Angle::D90
+    );
+    assert!(!applied.flipped);
+    assert_eq!(
+        upright
+            .exif()
+            .orientation(),
+        Some(1)
+    );
+    // the source keeps its metadata
+    assert_eq!(
+        image.get_orientation(),
+        6
+    );
+
+    let image = rgb_image()
+        .copy()
+        .unwrap();
+    image.set_int(
+        "orientation",
+        5,
+    );
+    let (unchanged, applied) = image
+        .auto_orient(false)
+        .unwrap();
+    assert!(applied.is_identity());
+    assert_eq!(
+        unchanged.get_orientation(),
+        5
+    );
+    let (transposed, applied) = image
+        .auto_orient(true)
+        .unwrap();
+    assert!(applied.flipped);
+    assert_eq!(
+        transposed
+            .getpoint(10, 3)
+            .unwrap(),
+        image
+            .getpoint(3, 10)
+            .unwrap()
+    );
+}
+
+/// Flips and crops to the left half, as a pipeline and as wrapper calls
+fn flip_and_crop() -> (
+    Pipeline,
+    VipsImage,
+) {
+    let pipeline = Pipeline::new(vec![
+        Step::new("flip").arg(
+            "direction",
+            "horizontal",
+        ),
+        Step::new("extract_area")
+            .arg("left", 0)
+            .arg("top", 0)
+            .arg(
+                "width",
+                32,
+            )
+            .arg(
+                "height",
+                48,
+            ),
+    ])
+    .unwrap();
+    let expected = rgb_image()
+        .flip(Direction::Horizontal)
+        .unwrap()
+        .extract_area(
+            0, 0, 32, 48,
+        )
+        .unwrap();
+    (
+        pipeline,
+        expected,
+    )
+}
+
+#[test]
+fn pipeline_runs_every_step() {
+    let (pipeline, expected) = flip_and_crop();
+    let image = rgb_image()
+        .copy()
+        .unwrap();
+    // running twice checks the input and intermediates are not released by a run
+    for _ in 0..2 {
+        let result = pipeline
+            .apply(&image)
+            .unwrap();
+        assert_eq!(
+            result.image_write_to_memory(),
+            expected.image_write_to_memory()
+        );
+    }
+    match pipeline
+        .run(&image)
+        .unwrap()
+    {
+        PipelineOutput::Image(result) => assert_eq!(
+            (
+                result.get_width(),
+                result.get_height()
+            ),
+            (32, 48)
+        ),
+        _ => panic!("Expected an image"),
+    }
+    assert!(pipeline
+        .apply_to_buffer(&image)
+        .is_err());
+    assert_eq!(
+        image.get_width(),
+        64
+    );
+
+    let mut steps = pipeline
+        .steps()
+        .to_vec();
+    steps.push(
+        Step::new("pngsave_buffer").arg(
+            "compression",
+            1,
+        ),
+    );
+    let pipeline = Pipeline::new(steps).unwrap();
+    let png = pipeline
+        .apply_to_buffer(&image)
+        .unwrap();
+    let decoded = VipsImage::new_from_buffer(&png, "").unwrap();
+    assert_eq!(
+        decoded.image_write_to_memory(),
+        expected.image_write_to_memory()
+    );
+    assert!(pipeline
+        .apply(&image)
+        .is_err());
+}
+
+#[test]
+fn pipeline_steps_are_validated() {
+    init();
+    let error = |steps: Vec<Step>| {
+        Pipeline::new(steps)
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(
+        error(vec![
+            Step::new("no_such_operation")
+        ])
+        .contains("is not a known operation")
+    );
+    assert!(
+        error(vec![
+            Step::new("extract_area")
+                .arg("left", 0)
+                .arg("top", 0)
+                .arg("width", 8)
+        ])
+        .contains("is missing required argument `height`")
+    );
+    assert!(
+        error(vec![
+            Step::new("pngsave_buffer"),
+            Step::new("flip").arg(
+                "direction",
+                "horizontal"
+            ),
+        ])
+        .contains("must be the last step")
+    );
+    assert!(
+        error(vec![
+            Step::new("flip").arg(
+                "direction",
+                "sideways"
+            )
+        ])
+        .contains("`sideways` is not one of")
+    );
+    assert!(error(vec![]).contains("no steps"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn pipeline_round_trips_through_json() {
+    let (pipeline, expected) = flip_and_crop();
+    let json = pipeline
+        .to_json()
+        .unwrap();
+    let loaded = Pipeline::from_json(&json).unwrap();
+    assert_eq!(
+        loaded.steps(),
+        pipeline.steps()
+    );
+    assert_eq!(
+        loaded
+            .apply(&rgb_image())
+            .unwrap()
+            .image_write_to_memory(),
+        expected.image_write_to_memory()
+    );
+    assert!(
+        Pipeline::from_json(r#"{"steps": [{"nickname": "flip", "args": {"direction": "up"}}]}"#)
+            .is_err()
+    );
+}